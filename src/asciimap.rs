@@ -0,0 +1,421 @@
+use std::char;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+
+use level::{self, Error, Level, RawLevel};
+use load::make_context;
+
+/// The character of an empty cell, in both tile and color grids.
+///
+/// Spaces are read as empty cells as well.
+pub const EMPTY: char = '.';
+/// The start of every grid row, so that rows keep their trailing cells and cannot be
+/// mistaken for directives.
+const ROW_START: char = '|';
+/// Characters for tiles whose names start with a character that is already taken.
+const SPARE_CHARS: &'static str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789\
+                                   #@$%&*+=-~^!?/\\<>()[]{}:;,'\"`_";
+/// Colors are written as digits in this base, so that `0`-`9` are followed by `a`-`z`.
+const COLOR_RADIX: u32 = 36;
+
+/// Returns an error for the byte range `lo..hi` of `contents`, showing where it is.
+fn error_at(contents: &str, lo: usize, hi: usize, msg: &str) -> Error {
+    let line_start = contents[..lo].rfind('\n').map_or(0, |i| i + 1);
+    let line = contents[..lo].matches('\n').count() + 1;
+    let column = contents[line_start..lo].chars().count() + 1;
+    let mut text = format!("line {}, column {}: {}\n", line, column, msg);
+    make_context(contents, lo, hi, &mut text);
+    Error::Text(text)
+}
+
+/// A grid of tiles or colors for a layer.
+struct Grid<'a> {
+    layer: &'a str,
+    colors: bool,
+    /// Where the header of the grid is.
+    header: (usize, usize),
+    /// The rows from the top, with the position of their first cell.
+    rows: Vec<(usize, &'a str)>,
+}
+
+#[inline]
+fn is_empty_cell(c: char) -> bool {
+    c == EMPTY || c == ' '
+}
+
+/// Reads a level in the text format.
+///
+/// The format consists of directives, one per line, and grids:
+///
+/// ```text
+/// name Example
+/// schema ladder
+/// size 3 2
+/// origin 0 2
+/// tile # wall
+/// tile o coin
+///
+/// layer ground
+/// |###
+/// |#o.
+/// colors ground
+/// |000
+/// |01.
+/// ```
+///
+/// `origin` gives the position of the top-left cell of every grid, and defaults to
+/// putting the bottom row of the tallest grid at `y = 0`. Each `tile` line gives the
+/// character for a tile, and each `layer` section holds a grid of tile characters, with
+/// `.` for empty cells. An optional `colors` section for the same layer gives the color
+/// of each tile as a digit, and missing colors are 0. The optional `size` gives the width
+/// and height of a bounded level. Lines starting with `#` outside of grids are comments.
+pub fn parse(contents: &str) -> Result<RawLevel, Error> {
+    let mut name = None;
+    let mut schema = None;
+    let mut origin = None;
+    let mut size = None;
+    let mut legend = HashMap::new();
+    let mut grids: Vec<Grid> = Vec::new();
+    let mut in_grid = false;
+
+    let mut offset = 0;
+    for line in contents.split('\n') {
+        let start = offset;
+        offset += line.len() + 1;
+        let line = line.trim_right_matches('\r');
+        let indent = line.len() - line.trim_left().len();
+        let trimmed = line.trim();
+        let lo = start + indent;
+        let hi = start + indent + trimmed.len();
+
+        if trimmed.starts_with(ROW_START) {
+            if !in_grid {
+                return Err(error_at(contents, lo, lo + 1, "grid row outside of a grid"));
+            }
+            let row = &line[indent + 1..];
+            grids.last_mut().expect("unreachable; no grid").rows.push((lo + 1, row));
+            continue;
+        }
+        in_grid = false;
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, rest) = match trimmed.find(char::is_whitespace) {
+            Some(i) => (&trimmed[..i], trimmed[i..].trim()),
+            None => (trimmed, ""),
+        };
+        match keyword {
+            "name" => name = Some(rest.to_owned()),
+            "schema" => schema = Some(rest.to_owned()),
+            "origin" => {
+                let numbers = rest.split_whitespace()
+                    .map(|n| n.parse::<i32>())
+                    .collect::<Result<Vec<_>, _>>();
+                match numbers {
+                    Ok(ref numbers) if numbers.len() == 2 => {
+                        origin = Some((numbers[0], numbers[1]))
+                    }
+                    _ => return Err(error_at(contents, lo, hi, "expected 'origin X Y'")),
+                }
+            }
+            "size" => {
+                let numbers = rest.split_whitespace()
+                    .map(|n| n.parse::<u32>())
+                    .collect::<Result<Vec<_>, _>>();
+                match numbers {
+                    Ok(ref numbers) if numbers.len() == 2 => {
+                        let numbers = (numbers[0], numbers[1]);
+                        if let Err(msg) = level::check_size(numbers) {
+                            return Err(error_at(contents, lo, hi, &msg));
+                        }
+                        size = Some(numbers)
+                    }
+                    _ => {
+                        let msg = "expected 'size WIDTH HEIGHT'";
+                        return Err(error_at(contents, lo, hi, msg));
+                    }
+                }
+            }
+            "tile" => {
+                let mut chars = rest.chars();
+                let c = chars.next();
+                let tile = chars.as_str().trim();
+                let c = match c {
+                    Some(c) if !tile.is_empty() => c,
+                    _ => {
+                        let msg = "expected 'tile CHARACTER NAME'";
+                        return Err(error_at(contents, lo, hi, msg));
+                    }
+                };
+                if is_empty_cell(c) || c == ROW_START {
+                    let msg = format!("{:?} cannot be used for a tile", c);
+                    return Err(error_at(contents, lo, hi, &msg));
+                }
+                if legend.insert(c, tile.to_owned()).is_some() {
+                    let msg = format!("{:?} is used for more than one tile", c);
+                    return Err(error_at(contents, lo, hi, &msg));
+                }
+            }
+            "layer" | "colors" => {
+                let colors = keyword == "colors";
+                if rest.is_empty() {
+                    let msg = format!("expected '{} LAYER'", keyword);
+                    return Err(error_at(contents, lo, hi, &msg));
+                }
+                if grids.iter().any(|grid| grid.layer == rest && grid.colors == colors) {
+                    let msg = format!("'{} {}' appears more than once", keyword, rest);
+                    return Err(error_at(contents, lo, hi, &msg));
+                }
+                grids.push(Grid {
+                    layer: rest,
+                    colors: colors,
+                    header: (lo, hi),
+                    rows: Vec::new(),
+                });
+                in_grid = true;
+            }
+            _ => {
+                let msg = format!("unknown directive {:?}", keyword);
+                return Err(error_at(contents, lo, lo + keyword.len(), &msg));
+            }
+        }
+    }
+
+    let name = name.ok_or_else(|| Error::Text("missing 'name' line".to_owned()))?;
+    let schema = schema.ok_or_else(|| Error::Text("missing 'schema' line".to_owned()))?;
+    let height = grids.iter().map(|grid| grid.rows.len()).max().unwrap_or(0);
+    let (ox, oy) = origin.unwrap_or((0, height as i32 - 1));
+
+    let mut layers = HashMap::new();
+    for grid in grids.iter().filter(|grid| grid.colors) {
+        if !grids.iter().any(|other| !other.colors && other.layer == grid.layer) {
+            let (lo, hi) = grid.header;
+            let msg = format!("colors given for {:?}, but there is no such layer", grid.layer);
+            return Err(error_at(contents, lo, hi, &msg));
+        }
+    }
+    for grid in grids.iter().filter(|grid| !grid.colors) {
+        let colors = grids.iter().find(|other| other.colors && other.layer == grid.layer);
+        let mut tiles = HashMap::new();
+        for (r, &(row_lo, row)) in grid.rows.iter().enumerate() {
+            let mut color_row = colors.and_then(|colors| colors.rows.get(r))
+                .map(|&(color_lo, color_row)| {
+                    color_row.char_indices().map(move |(i, c)| (color_lo + i, c))
+                });
+            for (col, (i, c)) in row.char_indices().enumerate() {
+                let color = match color_row.as_mut().and_then(|colors| colors.next()) {
+                    Some((_, c)) if is_empty_cell(c) => None,
+                    Some((color_lo, c)) => {
+                        match c.to_digit(COLOR_RADIX) {
+                            Some(color) => Some((color_lo, c, color)),
+                            None => {
+                                let msg = format!("{:?} is not a color", c);
+                                let hi = color_lo + c.len_utf8();
+                                return Err(error_at(contents, color_lo, hi, &msg));
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                if is_empty_cell(c) {
+                    if let Some((color_lo, c, _)) = color {
+                        let msg = "color given for an empty cell";
+                        return Err(error_at(contents, color_lo, color_lo + c.len_utf8(), msg));
+                    }
+                    continue;
+                }
+                let tile = match legend.get(&c) {
+                    Some(tile) => tile,
+                    None => {
+                        let msg = format!("{:?} is not in the legend", c);
+                        let lo = row_lo + i;
+                        return Err(error_at(contents, lo, lo + c.len_utf8(), &msg));
+                    }
+                };
+                let pos = (ox + col as i32, oy - r as i32);
+                tiles.entry(tile.clone())
+                    .or_insert_with(Vec::new)
+                    .push((pos.0, pos.1, color.map_or(0, |(_, _, color)| color)));
+            }
+        }
+        layers.insert(grid.layer.to_owned(), tiles);
+    }
+
+    Ok(RawLevel {
+        name: name,
+        schema: schema,
+        size: size,
+        layers: layers,
+    })
+}
+
+/// Picks a distinct character for every tile name, preferring the first letter of the
+/// name in either case.
+fn assign_chars<'a>(names: &[&'a str]) -> Result<BTreeMap<&'a str, char>, Error> {
+    let mut used = HashSet::new();
+    let mut chars = BTreeMap::new();
+    for &name in names {
+        let first = name.chars().next();
+        let candidates = first.into_iter()
+            .chain(first.into_iter().flat_map(|c| c.to_uppercase()))
+            .chain(first.into_iter().flat_map(|c| c.to_lowercase()))
+            .chain(SPARE_CHARS.chars());
+        let mut found = None;
+        for c in candidates {
+            if !c.is_whitespace() && !c.is_control() && c != EMPTY && c != ROW_START &&
+               !used.contains(&c) {
+                found = Some(c);
+                break;
+            }
+        }
+        match found {
+            Some(c) => {
+                used.insert(c);
+                chars.insert(name, c);
+            }
+            None => {
+                let msg = "too many different tiles for the text format";
+                return Err(Error::Text(msg.to_owned()));
+            }
+        }
+    }
+    Ok(chars)
+}
+
+/// Writes a level in the text format, with every grid covering the occupied area.
+///
+/// Layers are written in sorted order, empty layers are left out, and colors are only
+/// written for layers with tiles that are not of color 0.
+pub fn write<W>(level: &Level, writer: &mut W) -> Result<(), Error>
+    where W: Write
+{
+    if level.name.contains('\n') || level.schema.contains('\n') {
+        let msg = "names cannot contain line breaks in the text format";
+        return Err(Error::Text(msg.to_owned()));
+    }
+    let mut names = level.layers
+        .values()
+        .flat_map(|layer| layer.iter().map(|(_, tile)| &tile.name[..]))
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    let chars = assign_chars(&names)?;
+    let (x1, y1, x2, y2) = level.occupied_bounds().unwrap_or((0, 0, -1, -1));
+
+    writeln!(writer, "name {}", level.name)?;
+    writeln!(writer, "schema {}", level.schema)?;
+    if let Some((w, h)) = level.size {
+        writeln!(writer, "size {} {}", w, h)?;
+    }
+    writeln!(writer, "origin {} {}", x1, y2)?;
+    if !chars.is_empty() {
+        writeln!(writer, "")?;
+    }
+    for (name, c) in &chars {
+        writeln!(writer, "tile {} {}", c, name)?;
+    }
+
+    let mut layers = level.layers
+        .iter()
+        .filter(|&(_, layer)| !layer.is_empty())
+        .collect::<Vec<_>>();
+    layers.sort_by(|a, b| a.0.cmp(b.0));
+    for (layer_name, layer) in layers {
+        writeln!(writer, "\nlayer {}", layer_name)?;
+        let mut colored = false;
+        for y in (y1..y2 + 1).rev() {
+            let row = (x1..x2 + 1)
+                .map(|x| match layer.get((x, y)) {
+                    Some(tile) => {
+                        colored |= tile.color != 0;
+                        chars[&tile.name[..]]
+                    }
+                    None => EMPTY,
+                })
+                .collect::<String>();
+            writeln!(writer, "{}{}", ROW_START, row)?;
+        }
+        if !colored {
+            continue;
+        }
+        writeln!(writer, "colors {}", layer_name)?;
+        for y in (y1..y2 + 1).rev() {
+            let mut row = String::new();
+            for x in x1..x2 + 1 {
+                let c = match layer.get((x, y)) {
+                    Some(tile) => {
+                        match char::from_digit(tile.color, COLOR_RADIX) {
+                            Some(c) => c,
+                            None => {
+                                let msg = format!("color {} is too large for the text format",
+                                                  tile.color);
+                                return Err(Error::Text(msg));
+                            }
+                        }
+                    }
+                    None => EMPTY,
+                };
+                row.push(c);
+            }
+            writeln!(writer, "{}{}", ROW_START, row)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use level::{Error, Layer, Level, Tile};
+
+    use super::{parse, write};
+
+    #[test]
+    fn round_trip() {
+        let mut level = Level::new("round trip", "ladder");
+        level.size = Some((30, 20));
+        let mut ground = Layer::new();
+        for x in -4..3 {
+            ground.set((x, -3), Tile::new("wall", 0));
+        }
+        ground.set((-4, 2), Tile::new("wall", 11));
+        let mut items = Layer::new();
+        items.set((0, 0), Tile::new("coin", 1));
+        items.set((1, -2), Tile::new("crate", 0));
+        items.set((2, 1), Tile::new("coin", 35));
+        level.layers.insert("ground".to_owned(), ground);
+        level.layers.insert("items".to_owned(), items);
+
+        let mut buffer = Vec::new();
+        write(&level, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.contains("\norigin -4 2\n"));
+        assert!(text.contains("\nsize 30 20\n"));
+        let raw = parse(&text).unwrap();
+        assert_eq!(Level::from_raw(raw), level);
+    }
+
+    #[test]
+    fn origin_places_the_top_left_cell() {
+        let text = "name a\nschema b\norigin 5 -1\ntile # wall\nlayer ground\n|#.\n|.#\n";
+        let level = Level::from_raw(parse(text).unwrap());
+        let ground = &level.layers["ground"];
+        assert_eq!(ground.get((5, -1)), Some(&Tile::new("wall", 0)));
+        assert_eq!(ground.get((6, -2)), Some(&Tile::new("wall", 0)));
+        assert_eq!(ground.iter().count(), 2);
+    }
+
+    #[test]
+    fn errors_show_line_and_column() {
+        let text = "name a\nschema b\ntile # wall\n\nlayer ground\n|#.#\n|.?#\n";
+        match parse(text) {
+            Err(Error::Text(msg)) => {
+                assert!(msg.starts_with("line 7, column 3: '?' is not in the legend"),
+                        "unexpected message: {}",
+                        msg)
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use level::{Level, Tile};
+
+/// A way to mirror or rotate a rectangular area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    FlipHorizontal,
+    FlipVertical,
+    /// A quarter turn clockwise, as seen on screen.
+    Rotate90,
+    Rotate180,
+}
+
+impl Transform {
+    /// Maps an offset within an area of the given size to its transformed offset.
+    #[inline]
+    pub fn apply(self, offset: (i32, i32), size: (i32, i32)) -> (i32, i32) {
+        let (x, y) = offset;
+        let (w, h) = size;
+        match self {
+            Transform::FlipHorizontal => (w - 1 - x, y),
+            Transform::FlipVertical => (x, h - 1 - y),
+            // The model y axis points up, so clockwise on screen is clockwise here too.
+            Transform::Rotate90 => (y, w - 1 - x),
+            Transform::Rotate180 => (w - 1 - x, h - 1 - y),
+        }
+    }
+
+    /// Returns the size of an area after the transform.
+    #[inline]
+    pub fn size(self, size: (i32, i32)) -> (i32, i32) {
+        match self {
+            Transform::Rotate90 => (size.1, size.0),
+            _ => size,
+        }
+    }
+}
+
+/// A copied tile, positioned relative to the bottom-left corner of the copied area.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipCell {
+    /// The layer the tile was copied from, or `None` to paste into the current layer.
+    pub layer: Option<String>,
+    pub offset: (i32, i32),
+    pub tile: Tile,
+}
+
+/// A rectangular area of tiles that can be pasted elsewhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clipboard {
+    /// The width and height of the copied area in cells.
+    pub size: (i32, i32),
+    pub cells: Vec<ClipCell>,
+}
+
+impl Clipboard {
+    /// Copies the tiles inside the inclusive span `(x, y, x', y')`.
+    ///
+    /// If `all_layers` is set, every layer is copied and pasted back into the layer it
+    /// came from; otherwise only `current` is copied.
+    pub fn copy(level: &Level,
+                current: &str,
+                all_layers: bool,
+                span: (i32, i32, i32, i32))
+                -> Clipboard {
+        let (x1, y1, x2, y2) = span;
+        let mut cells = Vec::new();
+        for (layer_name, layer) in &level.layers {
+            if !all_layers && layer_name != current {
+                continue;
+            }
+            for (pos, tile) in layer.iter_span(span) {
+                cells.push(ClipCell {
+                    layer: if all_layers {
+                        Some(layer_name.clone())
+                    } else {
+                        None
+                    },
+                    offset: (pos.0 - x1, pos.1 - y1),
+                    tile: tile.clone(),
+                });
+            }
+        }
+        Clipboard {
+            size: (x2 - x1 + 1, y2 - y1 + 1),
+            cells: cells,
+        }
+    }
+
+    /// Returns the inclusive span covered when pasting with the corner at `anchor`.
+    #[inline]
+    pub fn span_at(&self, anchor: (i32, i32)) -> (i32, i32, i32, i32) {
+        (anchor.0, anchor.1, anchor.0 + self.size.0 - 1, anchor.1 + self.size.1 - 1)
+    }
+
+    /// Returns a mirrored or rotated copy of the clipboard.
+    pub fn transformed(&self, transform: Transform) -> Clipboard {
+        Clipboard {
+            size: transform.size(self.size),
+            cells: self.cells
+                .iter()
+                .map(|cell| {
+                    ClipCell { offset: transform.apply(cell.offset, self.size), ..cell.clone() }
+                })
+                .collect(),
+        }
+    }
+
+    /// Exchanges two colors, e.g. to give a mirrored area to the opposite player.
+    pub fn swap_colors(&mut self, a: u32, b: u32) {
+        for cell in &mut self.cells {
+            if cell.tile.color == a {
+                cell.tile.color = b;
+            } else if cell.tile.color == b {
+                cell.tile.color = a;
+            }
+        }
+    }
+}
@@ -1,14 +1,23 @@
-use glorious::{ResourceManager, Sprite};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use glorious::{Color, Renderer, ResourceManager, Sprite};
 use sdl2::rect::Rect;
+use sdl2_ttf::Font;
 
+use clipboard::Transform;
+use editor::{DEFAULT_BACKUPS, DEFAULT_HISTORY_DEPTH};
 use info::SpriteInfo;
+use text::Text;
+use toolbox::Tool;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Message {
-    Up,
-    Down,
-    Left,
-    Right,
+    ScrollStart(i32, i32),
+    ScrollStop(i32, i32),
+    PanKey(bool),
     PreExit,
     Exit,
 
@@ -19,21 +28,128 @@ pub enum Message {
     NextColor,
     PrevColor,
     Save,
+    Undo,
+    Redo,
+    SetTool(Tool),
+    Copy,
+    Cut,
+    Paste,
+    Delete,
+    Deselect,
+    ToggleAllLayers,
+    ShiftLevel(i32, i32),
+    NormalizeLevel,
+    TogglePalette,
+    SwapPaletteDock,
+    TransformSelection(Transform),
+    ToggleColorSwap,
+    NextSymmetry,
+    SetSymmetryCenter,
+    ToggleColorRotation,
+    Pick,
 
     LeftClickAt(i32, i32),
     LeftReleasedAt(i32, i32),
     RightClickAt(i32, i32),
     RightReleasedAt(i32, i32),
+    MiddleClickAt(i32, i32),
+    MiddleReleasedAt(i32, i32),
+    Zoom(i32),
     MouseMovedTo(i32, i32),
 }
 
+/// Options for an editing session.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// How many edits can be undone.
+    pub history_depth: usize,
+    /// How many previous versions of the level are kept when saving.
+    pub backups: usize,
+    /// The two colors exchanged when transforming with color swapping on, or `None` for
+    /// the first two.
+    pub color_swap: Option<(u32, u32)>,
+    /// A TrueType font for text in the editor, used instead of the one in the schema.
+    pub font: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            backups: DEFAULT_BACKUPS,
+            color_swap: None,
+            font: None,
+        }
+    }
+}
+
+pub const WINDOW_TITLE: &'static str = "La bonega karelizisto";
+
 pub struct State<'a> {
     pub resources: ResourceManager<'a, 'static>,
+    font: Font<'a>,
+    /// Rendered texts by their content and RGBA color.
+    texts: RefCell<HashMap<(String, (u8, u8, u8, u8)), Rc<Text>>>,
+    title: RefCell<String>,
 }
 
 impl<'a> State<'a> {
-    pub fn new(resources: ResourceManager<'a, 'static>) -> State<'a> {
-        State { resources: resources }
+    pub fn new(resources: ResourceManager<'a, 'static>, font: Font<'a>) -> State<'a> {
+        State {
+            resources: resources,
+            font: font,
+            texts: RefCell::new(HashMap::new()),
+            title: RefCell::new(WINDOW_TITLE.to_owned()),
+        }
+    }
+
+    /// Returns the text rendered in the given color, or `None` if it could not be rendered.
+    ///
+    /// Every text is rendered once per color and kept for later frames.
+    pub fn text(&self, renderer: &mut Renderer, text: &str, color: Color) -> Option<Rc<Text>> {
+        let Color(r, g, b, a) = color;
+        let key = (text.to_owned(), (r, g, b, a));
+        if let Some(rendered) = self.texts.borrow().get(&key) {
+            return Some(rendered.clone());
+        }
+        match Text::render(renderer, &self.font, text, color) {
+            Ok(rendered) => {
+                let rendered = Rc::new(rendered);
+                self.texts.borrow_mut().insert(key, rendered.clone());
+                Some(rendered)
+            }
+            Err(err) => {
+                warn!("{}", err);
+                None
+            }
+        }
+    }
+
+    /// Draws text centered on the given view position.
+    pub fn render_text_centered(&self,
+                                renderer: &mut Renderer,
+                                text: &str,
+                                center: (i32, i32),
+                                color: Color) {
+        if let Some(rendered) = self.text(renderer, text, color) {
+            let pos = (center.0 - rendered.width as i32 / 2,
+                       center.1 - rendered.height as i32 / 2);
+            rendered.draw(renderer, pos);
+        }
+    }
+
+    /// Sets the title of the editor window.
+    pub fn set_title(&self, renderer: &mut Renderer, title: &str) {
+        let mut current = self.title.borrow_mut();
+        if *current == title {
+            return;
+        }
+        if let Some(window) = renderer.window_mut() {
+            if let Err(err) = window.set_title(title) {
+                return warn!("Could not set the window title to {:?}: {}", title, err);
+            }
+        }
+        *current = title.to_owned();
     }
 
     #[inline]
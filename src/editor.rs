@@ -1,20 +1,37 @@
 use std::cmp;
-use std::collections::{BTreeSet, HashMap};
-use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use glorious::{Behavior, Color, Renderer};
 use sdl2::rect::Rect;
 
-use common::{Message, State};
+use clipboard::{Clipboard, Transform};
+use common::{Message, State, WINDOW_TITLE};
 use info::SpriteInfo;
-use level::{Layer, Level, Point};
-use toolbox::Tool;
+use level::{self, Layer, Level, Tile};
+use palette::{Dock, Palette};
+use symmetry::{self, Symmetry};
+use toolbox::{self, Tool};
+
+/// How many cells per second the view scrolls while a direction key is held.
+const SCROLL_SPEED: f64 = 12.0;
+
+/// How many pixels a middle click may move before it is a pan rather than a pick.
+const PAN_THRESHOLD: i32 = 3;
+
+/// The widths of the visible model area, from most to least zoomed in.
+const ZOOM_LEVELS: &'static [i32] = &[4, 8, 12, 16, 20, 24, 32, 40, 48, 64, 80, 100, 128, 160,
+                                      200];
 
 #[derive(Debug, Clone)]
 pub struct Viewport {
     // (x, y, x', y')
     pub model: (i32, i32, i32, i32),
     pub view: (i32, i32, i32, i32),
+    /// How far the model is shifted on the view, in view pixels, for smooth panning.
+    pub offset: (i32, i32),
 }
 
 fn convert(value: (i32, i32), from: (i32, i32, i32, i32), to: (i32, i32, i32, i32)) -> (i32, i32) {
@@ -43,27 +60,106 @@ fn convert_rect(rect: Rect, from: (i32, i32, i32, i32), to: (i32, i32, i32, i32)
     Rect::new(x1, y1, (x2 - x1) as u32, (y2 - y1) as u32)
 }
 
+/// A drag that moves the view.
+#[derive(Debug, Clone)]
+struct Pan {
+    button: u8,
+    origin: Viewport,
+    start: (i32, i32),
+    moved: bool,
+}
+
+#[inline]
+fn shift_rect(rect: Rect, delta: (i32, i32)) -> Rect {
+    Rect::new(rect.x() + delta.0, rect.y() + delta.1, rect.width(), rect.height())
+}
+
 impl Viewport {
     #[inline]
     pub fn model_to_view(&self, point: (i32, i32)) -> (i32, i32) {
-        convert(point, self.model, self.view)
+        let (x, y) = convert(point, self.model, self.view);
+        (x + self.offset.0, y + self.offset.1)
     }
 
     #[inline]
     pub fn view_to_model(&self, point: (i32, i32)) -> (i32, i32) {
+        let point = (point.0 - self.offset.0, point.1 - self.offset.1);
         convert(point, self.view, self.model)
     }
 
     #[inline]
     pub fn model_to_view_rect(&self, rect: Rect) -> Rect {
-        convert_rect(rect, self.model, self.view)
+        shift_rect(convert_rect(rect, self.model, self.view), self.offset)
     }
 
     #[inline]
     pub fn view_to_model_rect(&self, rect: Rect) -> Rect {
+        let rect = shift_rect(rect, (-self.offset.0, -self.offset.1));
         convert_rect(rect, self.view, self.model)
     }
 
+    /// Converts a view point to model coordinates in half cells.
+    #[inline]
+    pub fn view_to_model_halves(&self, point: (i32, i32)) -> (i32, i32) {
+        let (x1, y1, x2, y2) = self.model;
+        let point = (point.0 - self.offset.0, point.1 - self.offset.1);
+        convert(point, self.view, (2 * x1, 2 * y1, 2 * x2, 2 * y2))
+    }
+
+    /// Converts model coordinates in half cells to a view point.
+    #[inline]
+    pub fn model_halves_to_view(&self, point: (i32, i32)) -> (i32, i32) {
+        let (x1, y1, x2, y2) = self.model;
+        let (x, y) = convert(point, (2 * x1, 2 * y1, 2 * x2, 2 * y2), self.view);
+        (x + self.offset.0, y + self.offset.1)
+    }
+
+    /// Returns the signed size of a cell in view pixels along each axis.
+    #[inline]
+    pub fn cell_size(&self) -> (f64, f64) {
+        let (x1, y1, x2, y2) = self.model;
+        let (v1, w1, v2, w2) = self.view;
+        ((v2 - v1) as f64 / (x2 - x1) as f64, (w2 - w1) as f64 / (y2 - y1) as f64)
+    }
+
+    /// Moves the model on the view by the given number of view pixels.
+    ///
+    /// Whole cells are moved into the model area, so that the offset stays within a cell.
+    pub fn pan(&mut self, delta: (i32, i32)) {
+        let (sx, sy) = self.cell_size();
+        let ox = (self.offset.0 + delta.0) as f64;
+        let oy = (self.offset.1 + delta.1) as f64;
+        let kx = (ox / sx).floor();
+        let ky = (oy / sy).floor();
+        self.translate((-kx as i32, -ky as i32));
+        self.offset = ((ox - kx * sx).round() as i32, (oy - ky * sy).round() as i32);
+    }
+
+    /// Returns the number of cells visible horizontally.
+    #[inline]
+    pub fn width(&self) -> i32 {
+        self.model.2 - self.model.0
+    }
+
+    /// Scales the model area to the given width, keeping the model point under `anchor`
+    /// in place.
+    ///
+    /// The height follows from the aspect ratio of the view.
+    pub fn zoom_to(&mut self, anchor: (i32, i32), width: i32) {
+        let (x1, y1, x2, y2) = self.model;
+        let (v1, w1, v2, w2) = self.view;
+        let height = (width as f64 * (w2 - w1).abs() as f64 / (v2 - v1).abs() as f64).round();
+        let scale_x = width as f64 / (x2 - x1) as f64;
+        let scale_y = height / (y2 - y1) as f64;
+        let (ax, ay) = (anchor.0 - self.offset.0, anchor.1 - self.offset.1);
+        let ax = x1 as f64 + (ax - v1) as f64 * (x2 - x1) as f64 / (v2 - v1) as f64;
+        let ay = y1 as f64 + (ay - w1) as f64 * (y2 - y1) as f64 / (w2 - w1) as f64;
+        let nx = (ax - (ax - x1 as f64) * scale_x).round() as i32;
+        let ny = (ay - (ay - y1 as f64) * scale_y).round() as i32;
+        self.model = (nx, ny, nx + width, ny + height as i32);
+        self.offset = (0, 0);
+    }
+
     #[inline]
     pub fn translate(&mut self, delta: (i32, i32)) {
         self.model.0 += delta.0;
@@ -73,17 +169,217 @@ impl Viewport {
     }
 }
 
-fn insert_tile(layer: &mut Layer, tile: &str, pos: (i32, i32), color: u32) {
-    remove_tile(layer, pos);
-    if !layer.contains_key(tile) {
-        layer.insert(tile.to_owned(), BTreeSet::new());
+/// Sets the tile at `pos` in the named layer, returning what was there before.
+fn set_tile(level: &mut Level,
+            layer_name: &str,
+            pos: (i32, i32),
+            tile: Option<&Tile>)
+            -> Option<Tile> {
+    if !level.layers.contains_key(layer_name) {
+        level.layers.insert(layer_name.to_owned(), Layer::new());
     }
-    layer.get_mut(tile).expect("unreachable; insert failed").insert(Point(pos.0, pos.1, color));
+    let layer = level.layers.get_mut(layer_name).expect("unreachable; insert failed");
+    match tile {
+        Some(tile) => layer.set(pos, tile.clone()),
+        None => layer.remove(pos),
+    }
+}
+
+/// Removes every tile inside the inclusive span on the given layers.
+fn clear_span(history: &mut History,
+              level: &mut Level,
+              layers: &[String],
+              span: (i32, i32, i32, i32)) {
+    for layer_name in layers {
+        let positions = match level.layers.get(layer_name) {
+            Some(layer) => {
+                layer.iter_span(span)
+                    .map(|(pos, _)| (pos.0, pos.1))
+                    .collect::<Vec<_>>()
+            }
+            None => continue,
+        };
+        for pos in positions {
+            history.apply(level, layer_name, pos, None);
+        }
+    }
+}
+
+/// Places the tiles of a clipboard with its corner at `anchor`.
+///
+/// Tiles that the schema does not allow in their layer are left out.
+fn paste(history: &mut History,
+         level: &mut Level,
+         tile_sprites: &HashMap<String, SpriteInfo>,
+         current: &str,
+         clipboard: &Clipboard,
+         anchor: (i32, i32)) {
+    for cell in &clipboard.cells {
+        let layer = cell.layer.as_ref().map(|s| &s[..]).unwrap_or(current);
+        if !allows_layer(tile_sprites, &cell.tile.name, layer) {
+            continue;
+        }
+        let pos = (anchor.0 + cell.offset.0, anchor.1 + cell.offset.1);
+        history.apply(level, layer, pos, Some(cell.tile.clone()));
+    }
+}
+
+/// Returns whether the schema allows the named tile in the named layer.
+#[inline]
+fn allows_layer(tile_sprites: &HashMap<String, SpriteInfo>, tile: &str, layer: &str) -> bool {
+    tile_sprites.get(tile).map_or(false, |sprite| sprite.allows_layer(layer))
 }
 
-fn remove_tile(layer: &mut Layer, pos: (i32, i32)) {
-    for positions in layer.values_mut() {
-        positions.remove(&Point(pos.0, pos.1, 0));
+/// A change of a single cell, remembering both what was replaced and what replaced it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileChange {
+    pub layer: String,
+    pub pos: (i32, i32),
+    pub before: Option<Tile>,
+    pub after: Option<Tile>,
+}
+
+/// A group of changes that is undone and redone as one step.
+pub type Edit = Vec<TileChange>;
+
+/// Command-based undo/redo history for a level.
+///
+/// Every edit gets a unique id, so that the state of the level can be identified by the
+/// id of the latest edit, even after undoing and redoing.
+#[derive(Debug, Clone)]
+pub struct History {
+    undo: VecDeque<(u64, Edit)>,
+    redo: Vec<(u64, Edit)>,
+    pending: Option<(u64, Edit)>,
+    depth: usize,
+    /// The state below the oldest edit that can be undone.
+    base: u64,
+    next_id: u64,
+}
+
+pub const DEFAULT_HISTORY_DEPTH: usize = 100;
+
+impl History {
+    /// Creates a history that remembers at most `depth` edits.
+    pub fn new(depth: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            pending: None,
+            depth: depth,
+            base: 0,
+            next_id: 1,
+        }
+    }
+
+    /// Returns an id for the current state of the level.
+    ///
+    /// The id is the same whenever the level is in the same state, e.g. after an edit has
+    /// been undone and redone.
+    pub fn state(&self) -> u64 {
+        match self.pending {
+            Some((id, ref edit)) if !edit.is_empty() => id,
+            _ => self.undo.back().map_or(self.base, |&(id, _)| id),
+        }
+    }
+
+    #[inline]
+    fn new_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Starts grouping the following changes into a single edit.
+    pub fn begin(&mut self) {
+        self.commit();
+        let id = self.new_id();
+        self.pending = Some((id, Edit::new()));
+    }
+
+    /// Finishes the current group of changes, if any.
+    pub fn commit(&mut self) {
+        if let Some((id, edit)) = self.pending.take() {
+            self.push(id, edit);
+        }
+    }
+
+    fn push(&mut self, id: u64, edit: Edit) {
+        if edit.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.undo.push_back((id, edit));
+        while self.undo.len() > self.depth {
+            if let Some((id, _)) = self.undo.pop_front() {
+                self.base = id;
+            }
+        }
+    }
+
+    /// Changes a cell in the level and records the change.
+    ///
+    /// Changes made outside of `begin`/`commit` become an edit of their own. Tiles are
+    /// not placed outside the bounds of the level, although they can still be erased there.
+    pub fn apply(&mut self,
+                 level: &mut Level,
+                 layer: &str,
+                 pos: (i32, i32),
+                 tile: Option<Tile>) {
+        if tile.is_some() && !level.contains(pos) {
+            return;
+        }
+        self.record(level, layer, pos, tile);
+    }
+
+    /// Changes a cell in the level and records the change, even outside of the bounds.
+    fn record(&mut self, level: &mut Level, layer: &str, pos: (i32, i32), tile: Option<Tile>) {
+        let before = set_tile(level, layer, pos, tile.as_ref());
+        if before == tile {
+            return;
+        }
+        let change = TileChange {
+            layer: layer.to_owned(),
+            pos: pos,
+            before: before,
+            after: tile,
+        };
+        match self.pending {
+            Some((_, ref mut edit)) => edit.push(change),
+            None => {
+                let id = self.new_id();
+                self.push(id, vec![change]);
+            }
+        }
+    }
+
+    /// Reverts the latest edit. Returns whether anything was undone.
+    pub fn undo(&mut self, level: &mut Level) -> bool {
+        self.commit();
+        match self.undo.pop_back() {
+            Some((id, edit)) => {
+                for change in edit.iter().rev() {
+                    set_tile(level, &change.layer, change.pos, change.before.as_ref());
+                }
+                self.redo.push((id, edit));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapplies the latest undone edit. Returns whether anything was redone.
+    pub fn redo(&mut self, level: &mut Level) -> bool {
+        self.commit();
+        match self.redo.pop() {
+            Some((id, edit)) => {
+                for change in &edit {
+                    set_tile(level, &change.layer, change.pos, change.after.as_ref());
+                }
+                self.undo.push_back((id, edit));
+                true
+            }
+            None => false,
+        }
     }
 }
 
@@ -101,9 +397,61 @@ pub struct Editor {
     tool: Tool,
     button_down: u8,
     prev_point: (i32, i32),
+    mouse_pos: (i32, i32),
+    drag_start: Option<(i32, i32)>,
+    selection: Option<(i32, i32, i32, i32)>,
+    clipboard: Option<Clipboard>,
+    all_layers: bool,
+    pasting: bool,
+    floating: Option<Clipboard>,
+    color_swap: Option<(u32, u32)>,
+    swap_on_transform: bool,
+    symmetry: Option<Symmetry>,
+    symmetry_center: (i32, i32),
+    rotate_colors: bool,
+    pan: Option<Pan>,
+    pan_key_down: bool,
+    /// Whether the keys for scrolling left, right, down and up are held.
+    scroll_held: [bool; 4],
+    scroll_remainder: (f64, f64),
+    last_tick: Instant,
     save_to: Option<PathBuf>,
+    backups: usize,
+    save_error: Option<String>,
+    history: History,
+    /// The state of the history that matches the file, if any.
+    saved_state: Option<u64>,
+    autosaved_state: Option<u64>,
+    last_autosave: Instant,
+    /// Whether the user is being asked what to do with unsaved changes before quitting.
+    exit_prompt: bool,
+    palette: Palette,
+}
+
+/// An answer to the prompt shown when quitting with unsaved changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Choice {
+    Save,
+    Discard,
+    Cancel,
+}
+
+/// The box of the exit prompt, in view coordinates.
+const PROMPT_BOX: (i32, i32, u32, u32) = (200, 220, 400, 160);
+const BUTTON_SIZE: (u32, u32) = (100, 32);
+
+/// How often unsaved changes are written to the autosave file.
+pub const AUTOSAVE_INTERVAL_SECS: u64 = 60;
+
+/// Returns the path of the autosave file for a level saved at the given path.
+#[inline]
+pub fn autosave_path(path: &Path) -> PathBuf {
+    level::sibling_path(path, ".autosave")
 }
 
+/// How many previous versions of the level are kept when saving.
+pub const DEFAULT_BACKUPS: usize = 3;
+
 impl Editor {
     pub fn new<C, P>(layers: Vec<String>,
                      tile_sprites: HashMap<String, SpriteInfo>,
@@ -125,26 +473,299 @@ impl Editor {
         let num_layers = layers.len();
         let mut tiles = tile_sprites.keys().cloned().collect::<Vec<_>>();
         tiles.sort();
+        let palette = Palette::new(tiles.len());
+        let colors: Vec<Color> = colors.into();
+        // Swapping needs two colors, so a schema with fewer has nothing to swap by default.
+        let color_swap = if colors.len() >= 2 { Some((0, 1)) } else { None };
         Editor {
             layers: layers,
             current_layer: num_layers - 1,
             tile_sprites: tile_sprites,
             tiles: tiles,
             current_tile: 0,
-            colors: colors.into(),
+            colors: colors,
             current_color: 0,
             level: level,
             viewport: Viewport {
                 model: (0, 0, 20, 15),
                 view: (0, 600, 800, 0),
+                offset: (0, 0),
             },
             tool: Tool::Paint,
             button_down: 0,
             prev_point: (0, 0),
+            mouse_pos: (0, 0),
+            drag_start: None,
+            selection: None,
+            clipboard: None,
+            all_layers: false,
+            pasting: false,
+            floating: None,
+            color_swap: color_swap,
+            swap_on_transform: false,
+            symmetry: None,
+            symmetry_center: (20, 15),
+            rotate_colors: false,
+            pan: None,
+            pan_key_down: false,
+            scroll_held: [false; 4],
+            scroll_remainder: (0.0, 0.0),
+            last_tick: Instant::now(),
             save_to: save_to.map(|p| p.into()),
+            backups: DEFAULT_BACKUPS,
+            save_error: None,
+            history: History::new(DEFAULT_HISTORY_DEPTH),
+            saved_state: Some(0),
+            autosaved_state: Some(0),
+            last_autosave: Instant::now(),
+            exit_prompt: false,
+            palette: palette,
+        }
+    }
+
+    /// Sets the two colors that are exchanged when transforming with color swapping on.
+    #[inline]
+    pub fn set_color_swap(&mut self, swap: Option<(u32, u32)>) {
+        if let Some((a, b)) = swap {
+            assert!(a < self.colors.len() as u32 && b < self.colors.len() as u32,
+                    "colors not known to schema: {}, {}",
+                    a,
+                    b);
+        }
+        self.color_swap = swap;
+    }
+
+    /// Sets how many previous versions of the level are kept when saving.
+    #[inline]
+    pub fn set_backups(&mut self, backups: usize) {
+        self.backups = backups;
+    }
+
+    /// Saves the level, if there is somewhere to save it. Returns whether it succeeded.
+    ///
+    /// A failure is logged and shown in the editor until the next successful save.
+    pub fn save(&mut self) -> bool {
+        let result = match self.save_to {
+            Some(ref path) => {
+                info!("Saving level to {:?}", path);
+                self.level
+                    .save_with_backups(path, self.backups)
+                    .map_err(|err| format!("Could not save level to {:?}: {}", path, err))
+            }
+            None => {
+                warn!("Editor is in no-save mode!");
+                return true;
+            }
+        };
+        match result {
+            Ok(()) => {
+                self.save_error = None;
+                self.saved_state = Some(self.history.state());
+                self.remove_autosave();
+                true
+            }
+            Err(msg) => {
+                error!("{}", msg);
+                self.save_error = Some(msg);
+                false
+            }
+        }
+    }
+
+    /// Returns whether the level has changed since it was last saved.
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.saved_state != Some(self.history.state())
+    }
+
+    /// Marks the level as differing from its file.
+    #[inline]
+    pub fn mark_dirty(&mut self) {
+        self.saved_state = None;
+    }
+
+    /// Returns the window title, naming the file and marking unsaved changes.
+    pub fn title(&self) -> String {
+        let name = match self.save_to {
+            Some(ref path) => {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.display().to_string())
+            }
+            None => "(no save)".to_owned(),
+        };
+        format!("{} — {}{}",
+                WINDOW_TITLE,
+                name,
+                if self.is_dirty() { " *" } else { "" })
+    }
+
+    /// Exits at once if everything is saved, and otherwise asks what to do.
+    fn request_exit(&mut self, queue: &mut Vec<Message>) {
+        if self.is_dirty() {
+            self.exit_prompt = true;
+        } else {
+            queue.push(Message::Exit);
+        }
+    }
+
+    /// Returns the buttons of the exit prompt with their labels and view rectangles.
+    fn prompt_buttons(&self) -> Vec<(Choice, &'static str, Rect)> {
+        let mut choices = Vec::new();
+        if self.save_to.is_some() {
+            choices.push((Choice::Save, "Save"));
+        }
+        choices.push((Choice::Discard, "Discard"));
+        choices.push((Choice::Cancel, "Cancel"));
+
+        let (bx, by, bw, bh) = PROMPT_BOX;
+        let (w, h) = BUTTON_SIZE;
+        let gap = 20;
+        let count = choices.len() as i32;
+        let total = count * w as i32 + (count - 1) * gap;
+        let x = bx + (bw as i32 - total) / 2;
+        let y = by + bh as i32 - h as i32 - 24;
+        choices.into_iter()
+            .enumerate()
+            .map(|(i, (choice, label))| {
+                (choice, label, Rect::new(x + i as i32 * (w as i32 + gap), y, w, h))
+            })
+            .collect()
+    }
+
+    fn answer_prompt(&mut self, choice: Choice, queue: &mut Vec<Message>) {
+        self.exit_prompt = false;
+        match choice {
+            Choice::Save => {
+                if self.save() {
+                    queue.push(Message::Exit);
+                } else {
+                    error!("Not exiting, since the level could not be saved");
+                }
+            }
+            Choice::Discard => {
+                info!("Discarding unsaved changes");
+                self.remove_autosave();
+                queue.push(Message::Exit);
+            }
+            Choice::Cancel => {}
+        }
+    }
+
+    /// Handles a message while the exit prompt is shown, ignoring editing commands.
+    fn handle_prompt(&mut self, message: Message, queue: &mut Vec<Message>) {
+        use common::Message::*;
+
+        match message {
+            ScrollStart(dx, dy) => self.scroll((dx, dy), true),
+            ScrollStop(dx, dy) => self.scroll((dx, dy), false),
+            PanKey(down) => self.pan_key_down = down,
+            MouseMovedTo(x, y) => self.mouse_pos = (x, y),
+            LeftReleasedAt(x, y) => self.mouse_release((x, y), 1),
+            RightReleasedAt(x, y) => self.mouse_release((x, y), 2),
+            MiddleReleasedAt(x, y) => self.mouse_release((x, y), 3),
+            LeftClickAt(x, y) => {
+                let hit = self.prompt_buttons()
+                    .into_iter()
+                    .find(|&(_, _, rect)| rect.contains((x, y)))
+                    .map(|(choice, _, _)| choice);
+                if let Some(choice) = hit {
+                    self.answer_prompt(choice, queue);
+                }
+            }
+            Save if self.save_to.is_some() => self.answer_prompt(Choice::Save, queue),
+            Deselect => self.answer_prompt(Choice::Cancel, queue),
+            _ => {}
+        }
+    }
+
+    fn render_prompt<'a>(&self, state: &State<'a>, renderer: &mut Renderer) {
+        renderer.set_draw_color(Color(0x00, 0x00, 0x00, 0x88));
+        renderer.fill_rect(Rect::new(0, 0, 800, 600)).unwrap();
+        let (bx, by, bw, bh) = PROMPT_BOX;
+        let prompt_box = Rect::new(bx, by, bw, bh);
+        renderer.set_draw_color(Color(0xee, 0xee, 0xee, 0xff));
+        renderer.fill_rect(prompt_box).unwrap();
+        draw_outline(renderer, prompt_box, Color(0x44, 0x44, 0x44, 0xff));
+        state.render_text_centered(renderer,
+                                   "Save changes before quitting?",
+                                   (bx + bw as i32 / 2, by + 48),
+                                   Color(0x22, 0x22, 0x22, 0xff));
+
+        for (choice, label, rect) in self.prompt_buttons() {
+            let hovered = rect.contains(self.mouse_pos);
+            let color = match choice {
+                Choice::Save => Color(0x33, 0x99, 0x44, 0xff),
+                Choice::Discard => Color(0xcc, 0x33, 0x22, 0xff),
+                Choice::Cancel => Color(0x88, 0x88, 0x88, 0xff),
+            };
+            renderer.set_draw_color(color);
+            renderer.fill_rect(rect).unwrap();
+            if hovered {
+                draw_outline(renderer, rect, Color(0x22, 0x22, 0x22, 0xff));
+            }
+            let center = (rect.x() + rect.width() as i32 / 2, rect.y() + rect.height() as i32 / 2);
+            state.render_text_centered(renderer, label, center, Color(0xff, 0xff, 0xff, 0xff));
+        }
+    }
+
+    /// Renders a banner with the reason of the last failed save, if it failed.
+    fn render_save_error<'a>(&self, state: &State<'a>, renderer: &mut Renderer) {
+        const PADDING: u32 = 4;
+        let msg = match self.save_error {
+            Some(ref msg) => msg,
+            None => return,
+        };
+        // Text is drawn on a single line, so only the first line of the message is shown.
+        let line = msg.lines().next().unwrap_or("");
+        let text = state.text(renderer, line, Color(0xff, 0xff, 0xff, 0xff));
+        let height = text.as_ref().map_or(8, |text| text.height + 2 * PADDING);
+        renderer.set_draw_color(Color(0xcc, 0x11, 0x11, 0xdd));
+        renderer.fill_rect(Rect::new(0, 0, 800, height)).unwrap();
+        if let Some(text) = text {
+            text.draw(renderer, (PADDING as i32, PADDING as i32));
+        }
+    }
+
+    /// Writes the level to the autosave file if it has unsaved changes that have not been
+    /// autosaved yet, and enough time has passed.
+    fn autosave(&mut self) {
+        if !self.is_dirty() || self.autosaved_state == Some(self.history.state()) ||
+           self.last_autosave.elapsed() < Duration::from_secs(AUTOSAVE_INTERVAL_SECS) {
+            return;
+        }
+        self.last_autosave = Instant::now();
+        let path = match self.save_to {
+            Some(ref path) => autosave_path(path),
+            None => return,
+        };
+        debug!("Autosaving to {:?}", path);
+        match self.level.save(&path) {
+            Ok(()) => self.autosaved_state = Some(self.history.state()),
+            Err(err) => error!("Could not autosave to {:?}: {}", path, err),
         }
     }
 
+    fn remove_autosave(&mut self) {
+        if let Some(ref path) = self.save_to {
+            let path = autosave_path(path);
+            if path.exists() {
+                if let Err(err) = fs::remove_file(&path) {
+                    warn!("Could not remove autosave {:?}: {}", path, err);
+                }
+            }
+        }
+        self.autosaved_state = Some(self.history.state());
+    }
+
+    /// Sets how many edits can be undone.
+    #[inline]
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history = History::new(depth);
+        self.saved_state = Some(self.history.state());
+        self.autosaved_state = Some(self.history.state());
+    }
+
     #[inline]
     pub fn next_layer(&mut self) {
         self.current_layer = cmp::min(self.current_layer + 1, self.layers.len() - 1);
@@ -158,11 +779,13 @@ impl Editor {
     #[inline]
     pub fn next_tile(&mut self) {
         self.current_tile = (self.current_tile + 1) % self.tiles.len();
+        self.palette.reveal(self.current_tile);
     }
 
     #[inline]
     pub fn prev_tile(&mut self) {
         self.current_tile = (self.current_tile + self.tiles.len() - 1) % self.tiles.len();
+        self.palette.reveal(self.current_tile);
     }
 
     #[inline]
@@ -175,37 +798,770 @@ impl Editor {
         self.current_color = (self.current_color + self.colors.len() - 1) % self.colors.len();
     }
 
+    #[inline]
+    pub fn undo(&mut self) {
+        // Undoing would end the group of the stroke in progress.
+        if self.button_down != 0 {
+            return;
+        }
+        if !self.history.undo(&mut self.level) {
+            info!("Nothing to undo");
+        }
+    }
+
+    #[inline]
+    pub fn redo(&mut self) {
+        if self.button_down != 0 {
+            return;
+        }
+        if !self.history.redo(&mut self.level) {
+            info!("Nothing to redo");
+        }
+    }
+
+    pub fn set_tool(&mut self, tool: Tool) {
+        if self.button_down != 0 {
+            return;
+        }
+        info!("Tool: {:?}", tool);
+        self.tool = tool;
+    }
+
+    #[inline]
+    fn layer_name(&self) -> &str {
+        &self.layers[self.current_layer]
+    }
+
+    /// Returns the layers affected by clipboard operations.
+    fn clip_layers(&self) -> Vec<String> {
+        if self.all_layers {
+            self.layers.clone()
+        } else {
+            vec![self.layer_name().to_owned()]
+        }
+    }
+
+    pub fn toggle_all_layers(&mut self) {
+        self.all_layers = !self.all_layers;
+        info!("Clipboard and shifting use all layers: {}", self.all_layers);
+    }
+
+    /// Moves the tiles of the current layer, or of every layer, by `delta` as one edit.
+    pub fn shift(&mut self, delta: (i32, i32)) {
+        if delta == (0, 0) || self.button_down != 0 {
+            return;
+        }
+        self.history.begin();
+        for layer_name in self.clip_layers() {
+            let tiles = match self.level.layers.get(&layer_name) {
+                Some(layer) => {
+                    layer.iter()
+                        .map(|(pos, tile)| ((pos.0, pos.1), tile.clone()))
+                        .collect::<Vec<_>>()
+                }
+                None => continue,
+            };
+            for &(pos, _) in &tiles {
+                self.history.record(&mut self.level, &layer_name, pos, None);
+            }
+            for (pos, tile) in tiles {
+                let pos = (pos.0 + delta.0, pos.1 + delta.1);
+                self.history.record(&mut self.level, &layer_name, pos, Some(tile));
+            }
+        }
+        self.history.commit();
+        if let Some((x1, y1, x2, y2)) = self.selection {
+            self.selection = Some((x1 + delta.0, y1 + delta.1, x2 + delta.0, y2 + delta.1));
+        }
+        info!("Shifted by ({}, {})", delta.0, delta.1);
+    }
+
+    /// Shifts the current layer, or every layer, so that the top-left corner of the
+    /// occupied area is at `(0, 0)`.
+    pub fn normalize(&mut self) {
+        let delta = if self.all_layers {
+            self.level.normalizing_shift(None)
+        } else {
+            self.level.normalizing_shift(Some(self.layer_name()))
+        };
+        self.shift(delta);
+    }
+
+    /// Copies the selected tiles. Returns whether anything was selected.
+    pub fn copy(&mut self) -> bool {
+        match self.selection {
+            Some(span) => {
+                let clipboard =
+                    Clipboard::copy(&self.level, self.layer_name(), self.all_layers, span);
+                info!("Copied {} tiles", clipboard.cells.len());
+                self.clipboard = Some(clipboard);
+                true
+            }
+            None => {
+                info!("Nothing selected");
+                false
+            }
+        }
+    }
+
+    pub fn cut(&mut self) {
+        if self.copy() {
+            self.delete_selection();
+        }
+    }
+
+    pub fn delete_selection(&mut self) {
+        if let Some(span) = self.selection {
+            let layers = self.clip_layers();
+            self.history.begin();
+            clear_span(&mut self.history, &mut self.level, &layers, span);
+            self.history.commit();
+        }
+    }
+
+    /// Starts placing the clipboard at the cursor.
+    pub fn start_paste(&mut self) {
+        if self.clipboard.is_some() {
+            self.pasting = true;
+        } else {
+            info!("The clipboard is empty");
+        }
+    }
+
+    /// Cancels pasting, or otherwise clears the selection.
+    pub fn deselect(&mut self) {
+        if self.pasting {
+            self.pasting = false;
+        } else {
+            self.selection = None;
+        }
+    }
+
+    pub fn toggle_color_swap(&mut self) {
+        self.swap_on_transform = !self.swap_on_transform;
+        info!("Swap colors when transforming: {}", self.swap_on_transform);
+    }
+
+    fn transform_clipboard(&self, clipboard: &Clipboard, transform: Transform) -> Clipboard {
+        let mut result = clipboard.transformed(transform);
+        if let (true, Some((a, b))) = (self.swap_on_transform, self.color_swap) {
+            result.swap_colors(a, b);
+        }
+        result
+    }
+
+    /// Mirrors or rotates what is being pasted, or else the selection in place, or else
+    /// the clipboard.
+    pub fn transform(&mut self, transform: Transform) {
+        if self.button_down != 0 {
+            return;
+        }
+        if !self.pasting {
+            if let Some(span) = self.selection {
+                let selected =
+                    Clipboard::copy(&self.level, self.layer_name(), self.all_layers, span);
+                let result = self.transform_clipboard(&selected, transform);
+                let layers = self.clip_layers();
+                let anchor = (span.0, span.1);
+                self.history.begin();
+                clear_span(&mut self.history, &mut self.level, &layers, span);
+                paste(&mut self.history,
+                      &mut self.level,
+                      &self.tile_sprites,
+                      &self.layers[self.current_layer],
+                      &result,
+                      anchor);
+                self.history.commit();
+                self.selection = Some(result.span_at(anchor));
+                return;
+            }
+        }
+        let result = match self.clipboard {
+            Some(ref clipboard) => self.transform_clipboard(clipboard, transform),
+            None => return info!("Nothing to transform"),
+        };
+        self.clipboard = Some(result);
+    }
+
+    /// Starts or stops scrolling in a direction, given as a unit step in model cells.
+    pub fn scroll(&mut self, direction: (i32, i32), held: bool) {
+        let index = match direction {
+            (-1, 0) => 0,
+            (1, 0) => 1,
+            (0, -1) => 2,
+            (0, 1) => 3,
+            _ => return warn!("Invalid scroll direction {:?}", direction),
+        };
+        self.scroll_held[index] = held;
+    }
+
+    /// Returns the direction the view scrolls in, from the held keys.
+    #[inline]
+    fn scroll_direction(&self) -> (i32, i32) {
+        let held = |index: usize| self.scroll_held[index] as i32;
+        (held(1) - held(0), held(3) - held(2))
+    }
+
+    /// Advances time-based state, such as autosaving and scrolling while keys are held.
+    pub fn tick(&mut self) {
+        self.autosave();
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_tick);
+        self.last_tick = now;
+        let direction = self.scroll_direction();
+        if direction == (0, 0) {
+            self.scroll_remainder = (0.0, 0.0);
+            return;
+        }
+        let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+        let (sx, sy) = self.viewport.cell_size();
+        let dx = self.scroll_remainder.0 - direction.0 as f64 * SCROLL_SPEED * seconds * sx;
+        let dy = self.scroll_remainder.1 - direction.1 as f64 * SCROLL_SPEED * seconds * sy;
+        self.scroll_remainder = (dx - dx.trunc(), dy - dy.trunc());
+        self.viewport.pan((dx.trunc() as i32, dy.trunc() as i32));
+    }
+
+    fn begin_pan(&mut self, view_coord: (i32, i32), button: u8) {
+        self.pan = Some(Pan {
+            button: button,
+            origin: self.viewport.clone(),
+            start: view_coord,
+            moved: false,
+        });
+    }
+
+    /// Ends a pan started by the given button. Returns whether there was one.
+    fn end_pan(&mut self, view_coord: (i32, i32), button: u8) -> bool {
+        match self.pan.take() {
+            Some(pan) => {
+                if pan.button != button {
+                    self.pan = Some(pan);
+                    return false;
+                }
+                // A middle click that did not move picks instead.
+                if button == 3 && !pan.moved {
+                    self.pick(view_coord);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn mouse_click(&mut self, view_coord: (i32, i32), button: u8) {
+        if self.button_down != 0 || self.pan.is_some() {
+            return;
+        }
+        // Clicks on the palette select a tile and never reach the level.
+        if self.palette.contains(view_coord) {
+            if button == 1 {
+                if let Some(index) = self.palette.tile_at(view_coord) {
+                    self.current_tile = index;
+                    info!("Tile: {:?}", self.tiles[index]);
+                }
+            }
+            return;
+        }
+        if button == 3 || (button == 1 && self.pan_key_down) {
+            return self.begin_pan(view_coord, button);
+        }
+        let pos = self.viewport.view_to_model(view_coord);
+        // A paste is finished by the click itself, so the drag that follows paints nothing.
+        if self.pasting {
+            self.pasting = false;
+            if button == 1 {
+                let clipboard = self.clipboard.take().expect("pasting without a clipboard");
+                self.history.begin();
+                paste(&mut self.history,
+                      &mut self.level,
+                      &self.tile_sprites,
+                      &self.layers[self.current_layer],
+                      &clipboard,
+                      pos);
+                self.history.commit();
+                self.selection = Some(clipboard.span_at(pos));
+                self.clipboard = Some(clipboard);
+            }
+            return;
+        }
+        if button == 1 && self.tool != Tool::Select && !self.brush_allowed() {
+            warn!("{:?} is not allowed in layer {:?}",
+                  self.tiles[self.current_tile],
+                  self.layers[self.current_layer]);
+        }
         self.prev_point = view_coord;
+        self.mouse_pos = view_coord;
         self.button_down = button;
-        self.mouse_move(view_coord);
+        self.history.begin();
+        match self.tool {
+            Tool::Paint => self.mouse_move(view_coord),
+            Tool::Fill => self.fill(view_coord, button == 2),
+            Tool::Rect | Tool::Line => self.drag_start = Some(pos),
+            Tool::Select => {
+                let selection = self.selection;
+                match selection {
+                    Some(span) if button == 1 && toolbox::contains(span, pos) => {
+                        self.drag_start = Some(pos);
+                        self.floating = Some(Clipboard::copy(&self.level,
+                                                             self.layer_name(),
+                                                             self.all_layers,
+                                                             span));
+                    }
+                    _ => {
+                        self.selection = None;
+                        if button == 1 {
+                            self.drag_start = Some(pos);
+                        }
+                    }
+                }
+            }
+        }
     }
 
-    pub fn paint(&mut self, view_coord: (i32, i32), erase: bool) {
-        let layer_name = &self.layers[self.current_layer];
+    pub fn mouse_release(&mut self, view_coord: (i32, i32), button: u8) {
+        if self.end_pan(view_coord, button) || self.button_down != button {
+            return;
+        }
+        let erase = button == 2;
+        if let Some(start) = self.drag_start.take() {
+            let end = self.viewport.view_to_model(view_coord);
+            match self.tool {
+                Tool::Rect => {
+                    for pos in toolbox::rect_cells(start, end) {
+                        self.paint_at(pos, erase);
+                    }
+                }
+                Tool::Line => {
+                    for pos in toolbox::line_cells(start, end) {
+                        self.paint_at(pos, erase);
+                    }
+                }
+                Tool::Select => {
+                    match self.floating.take() {
+                        Some(floating) => {
+                            self.move_selection(floating, (end.0 - start.0, end.1 - start.1))
+                        }
+                        None => self.selection = Some(toolbox::span(start, end)),
+                    }
+                }
+                Tool::Paint | Tool::Fill => {}
+            }
+        }
+        self.button_down = 0;
+        self.history.commit();
+    }
 
-        if !self.level.layers.contains_key(layer_name) {
-            self.level.layers.insert(layer_name.to_owned(), Layer::new());
+    /// Moves the selected tiles, which were captured in `floating`, by `delta` cells.
+    fn move_selection(&mut self, floating: Clipboard, delta: (i32, i32)) {
+        let span = match self.selection {
+            Some(span) => span,
+            None => return,
+        };
+        if delta == (0, 0) {
+            return;
         }
-        let layer = self.level.layers.get_mut(layer_name).expect("unreachable; insert failed");
-        let pos = self.viewport.view_to_model(view_coord);
-        let tile = &self.tiles[self.current_tile];
+        let layers = self.clip_layers();
+        clear_span(&mut self.history, &mut self.level, &layers, span);
+        let anchor = (span.0 + delta.0, span.1 + delta.1);
+        paste(&mut self.history,
+              &mut self.level,
+              &self.tile_sprites,
+              &self.layers[self.current_layer],
+              &floating,
+              anchor);
+        self.selection = Some(floating.span_at(anchor));
+    }
 
+    /// Returns what painting puts into a cell.
+    #[inline]
+    fn brush(&self, erase: bool) -> Option<Tile> {
         if erase {
-            remove_tile(layer, pos);
+            None
         } else {
-            insert_tile(layer, tile, pos, self.current_color as u32);
+            Some(Tile::new(self.tiles[self.current_tile].clone(), self.current_color as u32))
+        }
+    }
+
+    /// Returns whether the schema allows the current tile in the current layer.
+    #[inline]
+    fn brush_allowed(&self) -> bool {
+        allows_layer(&self.tile_sprites,
+                     &self.tiles[self.current_tile],
+                     &self.layers[self.current_layer])
+    }
+
+    /// Paints the cell at the given model position on the current layer.
+    ///
+    /// With symmetry enabled, the mirrored cells are painted as well, optionally each in
+    /// the next color. Nothing is painted if the current tile is not allowed in the layer.
+    pub fn paint_at(&mut self, pos: (i32, i32), erase: bool) {
+        if !erase && !self.brush_allowed() {
+            return;
+        }
+        let positions = match self.symmetry {
+            Some(mode) => symmetry::images(mode, self.symmetry_center, pos),
+            None => vec![pos],
+        };
+        for (i, pos) in positions.into_iter().enumerate() {
+            let tile = self.brush(erase).map(|mut tile| {
+                if self.rotate_colors {
+                    tile.color = (tile.color + i as u32) % self.colors.len() as u32;
+                }
+                tile
+            });
+            let layer_name = &self.layers[self.current_layer];
+            self.history.apply(&mut self.level, layer_name, pos, tile);
+        }
+    }
+
+    /// Picks the tile, color and layer of the topmost tile at the given view position.
+    pub fn pick(&mut self, view_coord: (i32, i32)) {
+        if self.palette.contains(view_coord) {
+            return;
+        }
+        let pos = self.viewport.view_to_model(view_coord);
+        for (i, layer_name) in self.layers.iter().enumerate().rev() {
+            let layer = match self.level.layers.get(layer_name) {
+                Some(layer) => layer,
+                None => continue,
+            };
+            if let Some(tile) = layer.get(pos) {
+                match self.tiles.iter().position(|name| *name == tile.name) {
+                    Some(index) => self.current_tile = index,
+                    None => return warn!("Cannot pick unknown tile {:?}", tile.name),
+                }
+                self.current_color = cmp::min(tile.color as usize, self.colors.len() - 1);
+                self.current_layer = i;
+                return info!("Picked {:?} ({}) from layer {:?}",
+                             tile.name,
+                             tile.color,
+                             layer_name);
+            }
+        }
+        info!("Nothing to pick at ({}, {})", pos.0, pos.1);
+    }
+
+    /// Zooms in by the given number of steps, or out if negative, around the cursor.
+    pub fn zoom(&mut self, steps: i32) {
+        let width = self.viewport.width();
+        let current = ZOOM_LEVELS.iter()
+            .position(|&w| w >= width)
+            .unwrap_or(ZOOM_LEVELS.len() - 1);
+        let index = cmp::max(0, cmp::min(current as i32 - steps, ZOOM_LEVELS.len() as i32 - 1));
+        let new_width = ZOOM_LEVELS[index as usize];
+        if new_width != width {
+            let anchor = self.mouse_pos;
+            self.viewport.zoom_to(anchor, new_width);
+            debug!("Zoom: {} cells wide", new_width);
+        }
+    }
+
+    pub fn next_symmetry(&mut self) {
+        self.symmetry = Symmetry::next(self.symmetry);
+        info!("Symmetry: {:?}", self.symmetry);
+    }
+
+    /// Moves the symmetry center to the nearest cell edge or cell middle at the cursor.
+    pub fn set_symmetry_center(&mut self) {
+        self.symmetry_center = self.viewport.view_to_model_halves(self.mouse_pos);
+        info!("Symmetry center: ({}, {})",
+              self.symmetry_center.0 as f64 / 2.0,
+              self.symmetry_center.1 as f64 / 2.0);
+    }
+
+    pub fn toggle_color_rotation(&mut self) {
+        self.rotate_colors = !self.rotate_colors;
+        info!("Rotate colors for symmetric strokes: {}", self.rotate_colors);
+    }
+
+    /// Returns the tile at a model position on the named layer.
+    pub fn tile_at(&self, layer_name: &str, pos: (i32, i32)) -> Option<&Tile> {
+        self.level.layers.get(layer_name).and_then(|layer| layer.get(pos))
+    }
+
+    /// Replaces the connected area of equal cells around the given view position.
+    ///
+    /// The fill is limited to the visible part of the level, so that empty space can be
+    /// filled as well, and to the bounds of the level if it has any.
+    pub fn fill(&mut self, view_coord: (i32, i32), erase: bool) {
+        if !erase && !self.brush_allowed() {
+            return;
+        }
+        let start = self.viewport.view_to_model(view_coord);
+        let replacement = self.brush(erase);
+        let target = self.tile_at(&self.layers[self.current_layer], start).cloned();
+        if target == replacement {
+            return;
+        }
+        let (x1, y1, x2, y2) = self.viewport.model;
+        let bounds = match self.level.bounds() {
+            Some((bx1, by1, bx2, by2)) => {
+                (cmp::max(x1, bx1),
+                 cmp::max(y1, by1),
+                 cmp::min(x2 - 1, bx2),
+                 cmp::min(y2 - 1, by2))
+            }
+            None => (x1, y1, x2 - 1, y2 - 1),
+        };
+        let region = {
+            let layer_name = &self.layers[self.current_layer];
+            toolbox::flood_fill(start,
+                                bounds,
+                                |pos| self.tile_at(layer_name, pos) == target.as_ref())
+        };
+        for pos in region {
+            self.paint_at(pos, erase);
+        }
+    }
+
+    /// Paints every cell between the previous and the given view position, so that fast
+    /// strokes leave no gaps.
+    pub fn paint_stroke(&mut self, view_coord: (i32, i32), erase: bool) {
+        let from = self.viewport.view_to_model(self.prev_point);
+        let to = self.viewport.view_to_model(view_coord);
+        for pos in toolbox::line_cells(from, to) {
+            self.paint_at(pos, erase);
         }
+        self.prev_point = view_coord;
     }
 
     pub fn mouse_move(&mut self, view_coord: (i32, i32)) {
+        self.mouse_pos = view_coord;
+        if let Some(ref mut pan) = self.pan {
+            let delta = (view_coord.0 - pan.start.0, view_coord.1 - pan.start.1);
+            if delta.0.abs() > PAN_THRESHOLD || delta.1.abs() > PAN_THRESHOLD {
+                pan.moved = true;
+            }
+            if pan.moved {
+                self.viewport = pan.origin.clone();
+                self.viewport.pan(delta);
+            }
+            return;
+        }
+        if self.tool != Tool::Paint {
+            return;
+        }
+        // The palette hides the cells under it, so strokes skip them and resume where the
+        // cursor leaves it.
+        if self.palette.contains(view_coord) {
+            self.prev_point = view_coord;
+            return;
+        }
+        if self.palette.contains(self.prev_point) {
+            self.prev_point = view_coord;
+        }
         match self.button_down {
             0 => {}
-            1 => self.paint(view_coord, false),
-            2 => self.paint(view_coord, true),
+            1 => self.paint_stroke(view_coord, false),
+            2 => self.paint_stroke(view_coord, true),
             _ => unreachable!(),
         }
     }
+
+    /// Renders the outline of the shape being dragged by the current tool.
+    fn render_preview(&self, renderer: &mut Renderer) {
+        let start = match self.drag_start {
+            Some(start) => start,
+            None => return,
+        };
+        let end = self.viewport.view_to_model(self.mouse_pos);
+        let color = if self.button_down == 2 {
+            Color(0xdd, 0x22, 0x22, 0xff)
+        } else {
+            Color(0x22, 0x22, 0xdd, 0xff)
+        };
+        match self.tool {
+            Tool::Rect => {
+                let (x1, y1, x2, y2) = toolbox::span(start, end);
+                let model_rect = Rect::new(x1, y1, (x2 - x1 + 1) as u32, (y2 - y1 + 1) as u32);
+                let view_rect = self.viewport.model_to_view_rect(model_rect);
+                draw_outline(renderer, view_rect, color);
+            }
+            Tool::Line => {
+                renderer.set_draw_color(color.mul_alpha(0x77));
+                for (x, y) in toolbox::line_cells(start, end) {
+                    let view_rect = self.viewport.model_to_view_rect(Rect::new(x, y, 1, 1));
+                    renderer.fill_rect(view_rect).unwrap();
+                }
+            }
+            Tool::Select => {
+                if self.floating.is_none() {
+                    let (x1, y1, x2, y2) = toolbox::span(start, end);
+                    let model_rect = Rect::new(x1, y1, (x2 - x1 + 1) as u32, (y2 - y1 + 1) as u32);
+                    let view_rect = self.viewport.model_to_view_rect(model_rect);
+                    draw_outline(renderer, view_rect, SELECTION_COLOR);
+                }
+            }
+            Tool::Paint | Tool::Fill => {}
+        }
+    }
+
+    /// Dims everything outside the bounds of the level.
+    fn render_bounds(&self, renderer: &mut Renderer) {
+        let (w, h) = match self.level.size {
+            Some(size) => size,
+            None => return,
+        };
+        // Only the visible part of the bounds is converted, since the whole of a large level
+        // would overflow the conversion.
+        let visible = |start: i32, end: i32, a: i32, b: i32| {
+            let (lo, hi) = (cmp::min(a, b) - 1, cmp::max(a, b) + 1);
+            (cmp::max(lo, cmp::min(start, hi)), cmp::max(lo, cmp::min(end, hi)))
+        };
+        let (mx1, my1, mx2, my2) = self.viewport.model;
+        let (x1, x2) = visible(0, w as i32, mx1, mx2);
+        let (y1, y2) = visible(0, h as i32, my1, my2);
+        let inside = self.viewport
+            .model_to_view_rect(Rect::new(x1, y1, (x2 - x1) as u32, (y2 - y1) as u32));
+        let (left, top) = (inside.x(), inside.y());
+        let right = left + inside.width() as i32;
+        let bottom = top + inside.height() as i32;
+        let clamp = |value: i32, max: i32| cmp::max(0, cmp::min(value, max));
+        let (left, right) = (clamp(left, 800), clamp(right, 800));
+        let (top, bottom) = (clamp(top, 600), clamp(bottom, 600));
+        // (x, y, width, height) of the areas above, below, left and right of the bounds.
+        let outside = [(0, 0, 800, top),
+                       (0, bottom, 800, 600 - bottom),
+                       (0, top, left, bottom - top),
+                       (right, top, 800 - right, bottom - top)];
+        renderer.set_draw_color(Color(0x00, 0x00, 0x00, 0x66));
+        for &(x, y, width, height) in outside.iter().filter(|area| area.2 > 0 && area.3 > 0) {
+            renderer.fill_rect(Rect::new(x, y, width as u32, height as u32)).unwrap();
+        }
+    }
+
+    /// Renders the tile palette, with the name of the tile under the cursor.
+    fn render_palette<'a>(&self, state: &State<'a>, renderer: &mut Renderer) {
+        const PADDING: u32 = 4;
+        if !self.palette.visible {
+            return;
+        }
+        renderer.set_draw_color(Color(0x22, 0x22, 0x22, 0xdd));
+        renderer.fill_rect(self.palette.rect()).unwrap();
+        for (index, rect) in self.palette.thumbnails() {
+            let name = &self.tiles[index];
+            let sprite = state.sprite(&self.tile_sprites[name]);
+            sprite.render_rect(renderer, rect);
+            // Dim the tiles that cannot be placed in the current layer.
+            if !allows_layer(&self.tile_sprites, name, &self.layers[self.current_layer]) {
+                renderer.set_draw_color(Color(0x22, 0x22, 0x22, 0xaa));
+                renderer.fill_rect(rect).unwrap();
+            }
+            if index == self.current_tile {
+                draw_outline(renderer, rect, Color(0xff, 0xff, 0xff, 0xff));
+            } else if rect.contains(self.mouse_pos) {
+                draw_outline(renderer, rect, Color(0xaa, 0xaa, 0xaa, 0xff));
+            }
+        }
+        if let Some(scrollbar) = self.palette.scrollbar() {
+            renderer.set_draw_color(Color(0x99, 0x99, 0x99, 0xcc));
+            renderer.fill_rect(scrollbar).unwrap();
+        }
+
+        let hovered = match self.palette.tile_at(self.mouse_pos) {
+            Some(index) => &self.tiles[index],
+            None => return,
+        };
+        let text = match state.text(renderer, hovered, Color(0xff, 0xff, 0xff, 0xff)) {
+            Some(text) => text,
+            None => return,
+        };
+        // Keep the label next to the cursor, but inside the window.
+        let (w, h) = (text.width + 2 * PADDING, text.height + 2 * PADDING);
+        let x = cmp::max(0, cmp::min(self.mouse_pos.0 + 16, 800 - w as i32));
+        let y = cmp::max(0, cmp::min(self.mouse_pos.1 + 16, 600 - h as i32));
+        renderer.set_draw_color(Color(0x00, 0x00, 0x00, 0xcc));
+        renderer.fill_rect(Rect::new(x, y, w, h)).unwrap();
+        text.draw(renderer, (x + PADDING as i32, y + PADDING as i32));
+    }
+
+    /// Renders the symmetry axes, or the center for rotational symmetry.
+    fn render_symmetry(&self, renderer: &mut Renderer) {
+        const WIDTH: u32 = 2;
+        let mode = match self.symmetry {
+            Some(mode) => mode,
+            None => return,
+        };
+        let (x, y) = self.viewport.model_halves_to_view(self.symmetry_center);
+        let (vx1, vy1, vx2, vy2) = self.viewport.view;
+        let (left, top) = (cmp::min(vx1, vx2), cmp::min(vy1, vy2));
+        let (width, height) = ((vx2 - vx1).abs() as u32, (vy2 - vy1).abs() as u32);
+        renderer.set_draw_color(Color(0xcc, 0x22, 0xcc, 0xcc));
+        if mode.mirrors_x() {
+            renderer.fill_rect(Rect::new(x - WIDTH as i32 / 2, top, WIDTH, height)).unwrap();
+        }
+        if mode.mirrors_y() {
+            renderer.fill_rect(Rect::new(left, y - WIDTH as i32 / 2, width, WIDTH)).unwrap();
+        }
+        if mode == Symmetry::Rotate2 || mode == Symmetry::Rotate4 {
+            renderer.fill_rect(Rect::new(x - 6, y - WIDTH as i32 / 2, 12, WIDTH)).unwrap();
+            renderer.fill_rect(Rect::new(x - WIDTH as i32 / 2, y - 6, WIDTH, 12)).unwrap();
+        }
+    }
+
+    /// Renders a translucent copy of the clipboard with its corner at `anchor`.
+    fn render_ghost<'a>(&self,
+                        state: &State<'a>,
+                        renderer: &mut Renderer,
+                        clipboard: &Clipboard,
+                        anchor: (i32, i32)) {
+        for cell in &clipboard.cells {
+            let model_rect = Rect::new(anchor.0 + cell.offset.0, anchor.1 + cell.offset.1, 1, 1);
+            let view_rect = self.viewport.model_to_view_rect(model_rect);
+            if let Some(info) = self.tile_sprites.get(&cell.tile.name) {
+                state.sprite(info).render_rect(renderer, view_rect);
+            }
+            renderer.set_draw_color(Color(0xff, 0xff, 0xff, 0x77));
+            renderer.fill_rect(view_rect).unwrap();
+        }
+        let (x1, y1, x2, y2) = clipboard.span_at(anchor);
+        let model_rect = Rect::new(x1, y1, (x2 - x1 + 1) as u32, (y2 - y1 + 1) as u32);
+        draw_outline(renderer, self.viewport.model_to_view_rect(model_rect), SELECTION_COLOR);
+    }
+
+    /// Renders the selection, along with whatever is being moved or pasted.
+    fn render_selection<'a>(&self, state: &State<'a>, renderer: &mut Renderer) {
+        let cursor = self.viewport.view_to_model(self.mouse_pos);
+        if self.pasting {
+            if let Some(ref clipboard) = self.clipboard {
+                self.render_ghost(state, renderer, clipboard, cursor);
+            }
+            return;
+        }
+        let (x1, y1, x2, y2) = match self.selection {
+            Some(span) => span,
+            None => return,
+        };
+        match (self.floating.as_ref(), self.drag_start) {
+            (Some(floating), Some(start)) => {
+                let anchor = (x1 + cursor.0 - start.0, y1 + cursor.1 - start.1);
+                self.render_ghost(state, renderer, floating, anchor);
+            }
+            _ => {
+                let model_rect = Rect::new(x1, y1, (x2 - x1 + 1) as u32, (y2 - y1 + 1) as u32);
+                let view_rect = self.viewport.model_to_view_rect(model_rect);
+                draw_outline(renderer, view_rect, SELECTION_COLOR);
+            }
+        }
+    }
+}
+
+const SELECTION_COLOR: Color = Color(0xee, 0xbb, 0x11, 0xff);
+
+/// Draws a two pixel wide outline just inside the given rectangle.
+fn draw_outline(renderer: &mut Renderer, rect: Rect, color: Color) {
+    const WIDTH: u32 = 2;
+    let (x, y, w, h) = (rect.x(), rect.y(), rect.width(), rect.height());
+    if w <= 2 * WIDTH || h <= 2 * WIDTH {
+        renderer.set_draw_color(color);
+        renderer.fill_rect(rect).unwrap();
+        return;
+    }
+    renderer.set_draw_color(color);
+    renderer.fill_rect(Rect::new(x, y, w, WIDTH)).unwrap();
+    renderer.fill_rect(Rect::new(x, y + (h - WIDTH) as i32, w, WIDTH)).unwrap();
+    renderer.fill_rect(Rect::new(x, y, WIDTH, h)).unwrap();
+    renderer.fill_rect(Rect::new(x + (w - WIDTH) as i32, y, WIDTH, h)).unwrap();
 }
 
 impl<'a> Behavior<State<'a>> for Editor {
@@ -215,11 +1571,13 @@ impl<'a> Behavior<State<'a>> for Editor {
         use common::Message::*;
 
         trace!("{:?}", message);
+        if self.exit_prompt {
+            return self.handle_prompt(message, queue);
+        }
         match message {
-            Up => self.viewport.translate((0, 1)),
-            Left => self.viewport.translate((-1, 0)),
-            Down => self.viewport.translate((0, -1)),
-            Right => self.viewport.translate((1, 0)),
+            ScrollStart(dx, dy) => self.scroll((dx, dy), true),
+            ScrollStop(dx, dy) => self.scroll((dx, dy), false),
+            PanKey(down) => self.pan_key_down = down,
 
             NextLayer => self.next_layer(),
             PrevLayer => self.prev_layer(),
@@ -227,42 +1585,65 @@ impl<'a> Behavior<State<'a>> for Editor {
             PrevTile => self.prev_tile(),
             PrevColor => self.prev_color(),
             NextColor => self.next_color(),
+            Undo => self.undo(),
+            Redo => self.redo(),
+            SetTool(tool) => self.set_tool(tool),
+            Copy => {
+                self.copy();
+            }
+            Cut => self.cut(),
+            Paste => self.start_paste(),
+            Delete => self.delete_selection(),
+            Deselect => self.deselect(),
+            ToggleAllLayers => self.toggle_all_layers(),
+            ShiftLevel(dx, dy) => self.shift((dx, dy)),
+            NormalizeLevel => self.normalize(),
+            TransformSelection(transform) => self.transform(transform),
+            ToggleColorSwap => self.toggle_color_swap(),
+            NextSymmetry => self.next_symmetry(),
+            SetSymmetryCenter => self.set_symmetry_center(),
+            ToggleColorRotation => self.toggle_color_rotation(),
+            Zoom(steps) if self.palette.contains(self.mouse_pos) => self.palette.scroll(steps),
+            Zoom(steps) => self.zoom(steps),
+            TogglePalette => self.palette.toggle(),
+            SwapPaletteDock => self.palette.swap_dock(),
+            Pick => {
+                let pos = self.mouse_pos;
+                self.pick(pos);
+            }
             Save => {
-                match self.save_to {
-                    Some(ref path) => {
-                        info!("Saving level to {:?}", path);
-                        self.level.save(path).unwrap();
-                    }
-                    None => warn!("Editor is in no-save mode!"),
-                }
+                self.save();
             }
 
             LeftClickAt(x, y) => self.mouse_click((x, y), 1),
             RightClickAt(x, y) => self.mouse_click((x, y), 2),
             MouseMovedTo(x, y) => self.mouse_move((x, y)),
-            LeftReleasedAt(..) |
-            RightReleasedAt(..) => self.button_down = 0,
+            LeftReleasedAt(x, y) => self.mouse_release((x, y), 1),
+            RightReleasedAt(x, y) => self.mouse_release((x, y), 2),
+            MiddleClickAt(x, y) => self.mouse_click((x, y), 3),
+            MiddleReleasedAt(x, y) => self.mouse_release((x, y), 3),
 
-            PreExit => {
-                if let Some(ref path) = self.save_to {
-                    info!("Saving level to {:?}", path);
-                    self.level.save(path).unwrap();
-                }
-                queue.push(Exit);
-            }
+            PreExit => self.request_exit(queue),
             Exit => unreachable!(),
         }
     }
 
+    /// Applies held keys and autosaves once per frame, after the messages of the frame.
+    fn update(&mut self, _state: &mut State<'a>, _queue: &mut Vec<Message>) {
+        self.tick();
+    }
+
     fn render(&mut self, state: &State<'a>, renderer: &mut Renderer) {
+        state.set_title(renderer, &self.title());
+
         // Render checkerboard pattern
-        for col in 0..100 {
-            for row in 0..100 {
-                let x = col as i32 + self.viewport.model.0;
-                let y = row as i32 + self.viewport.model.1;
+        // One extra cell on each side covers the area uncovered by a pan offset.
+        let (x1, y1, x2, y2) = self.viewport.model;
+        for x in x1 - 1..x2 + 1 {
+            for y in y1 - 1..y2 + 1 {
                 let model_rect = Rect::new(x, y, 1, 1);
                 let view_rect = self.viewport.model_to_view_rect(model_rect);
-                let color = if (col + row) % 2 == 0 {
+                let color = if (x + y) & 1 == 0 {
                     Color(200, 200, 200, 255)
                 } else {
                     Color(220, 220, 220, 255)
@@ -272,6 +1653,7 @@ impl<'a> Behavior<State<'a>> for Editor {
             }
         }
 
+        let visible = (x1 - 1, y1 - 1, x2, y2);
         for (i, layer_name) in self.layers[..self.current_layer + 1].iter().enumerate() {
             if i == self.current_layer {
                 renderer.set_draw_color(Color(0xff, 0xff, 0xff, 0x77));
@@ -281,26 +1663,37 @@ impl<'a> Behavior<State<'a>> for Editor {
                 Some(layer) => layer,
                 None => continue,
             };
-            for (tile, positions) in layer {
-                let sprite = state.sprite(&self.tile_sprites[tile]);
-                for pos in positions {
-                    let model_rect = Rect::new(pos.0 as i32, pos.1 as i32, 1, 1);
-                    let view_rect = self.viewport.model_to_view_rect(model_rect);
-                    sprite.render_rect(renderer, view_rect);
-                    renderer.set_draw_color(self.colors[pos.2 as usize].mul_alpha(0xbb));
-                    let hw = view_rect.width() / 2;
-                    let hh = view_rect.height() / 2;
-                    let color_rect =
-                        Rect::new(view_rect.x() + hw as i32, view_rect.y() + hh as i32, hw, hh);
-                    renderer.fill_rect(color_rect).unwrap();
-                }
+            for (pos, tile) in layer.iter_span(visible) {
+                let sprite = state.sprite(&self.tile_sprites[&tile.name]);
+                let model_rect = Rect::new(pos.0, pos.1, 1, 1);
+                let view_rect = self.viewport.model_to_view_rect(model_rect);
+                sprite.render_rect(renderer, view_rect);
+                renderer.set_draw_color(self.colors[tile.color as usize].mul_alpha(0xbb));
+                let hw = view_rect.width() / 2;
+                let hh = view_rect.height() / 2;
+                let color_rect =
+                    Rect::new(view_rect.x() + hw as i32, view_rect.y() + hh as i32, hw, hh);
+                renderer.fill_rect(color_rect).unwrap();
             }
         }
 
-        let info_box = Rect::new(700, 20, 80, 80);
+        self.render_bounds(renderer);
+        self.render_symmetry(renderer);
+        self.render_preview(renderer);
+        self.render_selection(state, renderer);
+
+        self.render_save_error(state, renderer);
+
+        // The info box stays in the top-right corner, left of the palette if it is there.
+        let right = if self.palette.visible && self.palette.dock == Dock::Right {
+            self.palette.rect().x()
+        } else {
+            800
+        };
+        let info_box = Rect::new(right - 100, 20, 80, 80);
         renderer.set_draw_color(Color(0x00, 0x00, 0x00, 0x77));
         renderer.fill_rect(info_box).unwrap();
-        let tile_rect = Rect::new(708, 28, 64, 64);
+        let tile_rect = Rect::new(right - 92, 28, 64, 64);
         let tile_name = &self.tiles[self.current_tile];
         let sprite = state.sprite(&self.tile_sprites[tile_name]);
         sprite.render_rect(renderer, tile_rect);
@@ -309,5 +1702,73 @@ impl<'a> Behavior<State<'a>> for Editor {
         let hh = tile_rect.height() / 2;
         let color_rect = Rect::new(tile_rect.x() + hw as i32, tile_rect.y() + hh as i32, hw, hh);
         renderer.fill_rect(color_rect).unwrap();
+
+        self.render_palette(state, renderer);
+
+        if self.exit_prompt {
+            self.render_prompt(state, renderer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use level::{Level, Tile};
+
+    use super::History;
+
+    fn tile_at(level: &Level, pos: (i32, i32)) -> Option<Tile> {
+        level.layers.get("ground").and_then(|layer| layer.get(pos)).cloned()
+    }
+
+    #[test]
+    fn drag_undoes_as_one_step() {
+        let mut level = Level::new("test", "test");
+        let mut history = History::new(10);
+        history.apply(&mut level, "ground", (1, 0), Some(Tile::new("wall", 2)));
+
+        history.begin();
+        for x in 0..3 {
+            history.apply(&mut level, "ground", (x, 0), Some(Tile::new("coin", 1)));
+        }
+        history.commit();
+        assert_eq!(tile_at(&level, (1, 0)), Some(Tile::new("coin", 1)));
+
+        assert!(history.undo(&mut level));
+        assert_eq!(tile_at(&level, (0, 0)), None);
+        assert_eq!(tile_at(&level, (1, 0)), Some(Tile::new("wall", 2)));
+        assert_eq!(tile_at(&level, (2, 0)), None);
+
+        assert!(history.redo(&mut level));
+        for x in 0..3 {
+            assert_eq!(tile_at(&level, (x, 0)), Some(Tile::new("coin", 1)));
+        }
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut level = Level::new("test", "test");
+        let mut history = History::new(10);
+        history.apply(&mut level, "ground", (0, 0), Some(Tile::new("wall", 0)));
+        assert!(history.undo(&mut level));
+        history.apply(&mut level, "ground", (5, 5), Some(Tile::new("coin", 0)));
+        assert!(!history.redo(&mut level));
+        assert_eq!(tile_at(&level, (0, 0)), None);
+        assert_eq!(tile_at(&level, (5, 5)), Some(Tile::new("coin", 0)));
+    }
+
+    #[test]
+    fn oldest_edit_is_evicted_at_depth() {
+        let mut level = Level::new("test", "test");
+        let mut history = History::new(2);
+        for x in 0..3 {
+            history.apply(&mut level, "ground", (x, 0), Some(Tile::new("wall", 0)));
+        }
+        assert!(history.undo(&mut level));
+        assert!(history.undo(&mut level));
+        assert!(!history.undo(&mut level));
+        assert_eq!(tile_at(&level, (0, 0)), Some(Tile::new("wall", 0)));
+        assert_eq!(tile_at(&level, (1, 0)), None);
+        assert_eq!(tile_at(&level, (2, 0)), None);
     }
 }
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::i32;
+use std::path::Path;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Renderer};
+use sdl2::surface::Surface;
+use sdl2_image::{LoadTexture, SaveSurface};
+
+use info::Schema;
+use level::Level;
+
+/// Returns the size in pixels of the cells from `lo` to `hi`, inclusive.
+///
+/// The size must fit in an `i32`, since cells are placed at signed pixel positions.
+fn pixel_size(lo: i32, hi: i32, cell_size: u32) -> Result<u32, String> {
+    if hi < lo {
+        return Err(format!("the area is inverted ({} > {})", lo, hi));
+    }
+    let size = (hi as i64 - lo as i64 + 1) as u64 * cell_size as u64;
+    if size > i32::MAX as u64 {
+        return Err(format!("the image would be {} pixels across, which is too large", size));
+    }
+    Ok(size as u32)
+}
+
+/// Renders a level to a PNG file without opening a window.
+///
+/// Layers are drawn in schema order, with the same color markers as in the editor.
+/// `area` is the inclusive model area `(x, y, x', y')` to draw, and defaults to the
+/// occupied part of the level. SDL2_image must be initialized.
+pub fn export_png<P>(level: &Level,
+                     schema: &Schema,
+                     path: P,
+                     cell_size: u32,
+                     area: Option<(i32, i32, i32, i32)>)
+                     -> Result<(), String>
+    where P: AsRef<Path>
+{
+    let (x1, y1, x2, y2) = match area.or_else(|| level.occupied_bounds()) {
+        Some(area) => area,
+        None => return Err("the level is empty".to_owned()),
+    };
+    if cell_size == 0 {
+        return Err("the cell size must be positive".to_owned());
+    }
+    let width = pixel_size(x1, x2, cell_size)?;
+    let height = pixel_size(y1, y2, cell_size)?;
+
+    let surface = Surface::new(width, height, PixelFormatEnum::RGBA8888)?;
+    let mut renderer = Renderer::from_surface(surface)?;
+    renderer.set_blend_mode(BlendMode::Blend);
+    renderer.set_draw_color(Color::RGBA(0, 0, 0, 0));
+    renderer.clear();
+
+    let mut textures = HashMap::new();
+    for layer_name in &schema.layers {
+        let layer = match level.layers.get(layer_name) {
+            Some(layer) => layer,
+            None => continue,
+        };
+        for (pos, tile) in layer.iter_span((x1, y1, x2, y2)) {
+            let info = match schema.tiles.get(&tile.name) {
+                Some(info) => info,
+                None => return Err(format!("tile not known to schema: {:?}", tile.name)),
+            };
+            if !textures.contains_key(&info.texture) {
+                let texture = renderer.load_texture(&schema.prefix.join(&info.texture))?;
+                textures.insert(info.texture.clone(), texture);
+            }
+            let texture = &textures[&info.texture];
+            let src = info.area.map(|(x, y, w, h)| Rect::new(x as i32, y as i32, w, h));
+            // The model y axis points up, like in the editor.
+            let dst = Rect::new((pos.0 - x1) * cell_size as i32,
+                                (y2 - pos.1) * cell_size as i32,
+                                cell_size,
+                                cell_size);
+            renderer.copy(texture, src, Some(dst));
+            let color = match schema.colors.get(tile.color as usize) {
+                Some(color) => color,
+                None => return Err(format!("color out of range: {}", tile.color)),
+            };
+            let alpha = (color.3 as u32 * 0xbb / 0xff) as u8;
+            renderer.set_draw_color(Color::RGBA(color.0, color.1, color.2, alpha));
+            let half = cell_size / 2;
+            let marker = Rect::new(dst.x() + half as i32, dst.y() + half as i32, half, half);
+            renderer.fill_rect(marker)?;
+        }
+    }
+
+    let surface = renderer.into_surface().ok_or("the renderer lost its surface")?;
+    surface.save(path)
+}
@@ -17,6 +17,8 @@ fn parse_color(text: &str) -> Result<Color, String> {
 pub struct SpriteInfo {
     pub texture: String,
     pub area: Option<(u32, u32, u32, u32)>,
+    /// The layers the tile may be placed in, or `None` for every layer.
+    pub layers: Option<Vec<String>>,
 }
 
 impl SpriteInfo {
@@ -25,8 +27,15 @@ impl SpriteInfo {
         Ok(SpriteInfo {
             texture: spec.texture,
             area: spec.area,
+            layers: spec.layers,
         })
     }
+
+    /// Returns whether the tile may be placed in the named layer.
+    #[inline]
+    pub fn allows_layer(&self, layer: &str) -> bool {
+        self.layers.as_ref().map_or(true, |layers| layers.iter().any(|l| l == layer))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -34,6 +43,8 @@ pub struct Schema {
     pub name: String,
     pub layers: Vec<String>,
     pub prefix: PathBuf,
+    /// A TrueType font for the editor, relative to the prefix.
+    pub font: Option<String>,
     pub colors: Vec<Color>,
     pub tiles: HashMap<String, SpriteInfo>,
 }
@@ -45,6 +56,7 @@ impl Schema {
             name: spec.name,
             layers: spec.layers,
             prefix: spec.prefix,
+            font: spec.font,
             colors: spec.colors.into_iter().map(|s| parse_color(&s)).collect()?,
             tiles: spec.tiles
                 .into_iter()
@@ -3,17 +3,54 @@ use std::path::PathBuf;
 use glorious::{BoxedInputMapper, Device, Game, ResourceManager};
 use sdl2;
 use sdl2::render::BlendMode;
-use sdl2::keyboard::Scancode;
+use sdl2::keyboard::{LCTRLMOD, LSHIFTMOD, Mod, RCTRLMOD, RSHIFTMOD, Scancode};
 use sdl2::mouse::Mouse;
 use sdl2_image::{self, INIT_JPG, INIT_PNG};
 use sdl2_ttf;
 
-use common::State;
+use clipboard::Transform;
+use common::{Settings, State, WINDOW_TITLE};
 use editor::Editor;
 use info::Schema;
 use level::Level;
+use toolbox::Tool;
 
-pub fn start_editor<P>(schema: Schema, level: Level, save_to: Option<P>)
+const FONT_SIZE: u16 = 16;
+
+/// Returns the path of the font to use for text in the editor, if one was given.
+///
+/// The editor needs a font to label its prompts, so it does not start without one.
+pub fn font_path(schema: &Schema, settings: &Settings) -> Option<PathBuf> {
+    settings.font
+        .clone()
+        .or_else(|| schema.font.as_ref().map(|font| schema.prefix.join(font)))
+}
+
+/// Returns the error for when neither the settings nor the schema give a font.
+pub fn no_font() -> String {
+    "the editor needs a font; set 'font' in the schema or pass --font PATH".to_owned()
+}
+
+#[inline]
+fn ctrl(keymod: Mod) -> bool {
+    keymod.intersects(LCTRLMOD | RCTRLMOD)
+}
+
+#[inline]
+fn shift(keymod: Mod) -> bool {
+    keymod.intersects(LSHIFTMOD | RSHIFTMOD)
+}
+
+/// Opens the editor window on a level.
+///
+/// `recovered` tells whether the level was restored from an autosave, and so differs from
+/// its file. Fails if the font cannot be loaded.
+pub fn start_editor<P>(schema: Schema,
+                       level: Level,
+                       save_to: Option<P>,
+                       settings: Settings,
+                       recovered: bool)
+                       -> Result<(), String>
     where P: Into<PathBuf>
 {
     use sdl2::event::Event::*;
@@ -23,7 +60,6 @@ pub fn start_editor<P>(schema: Schema, level: Level, save_to: Option<P>)
 
     // Load settings
 
-    const WINDOW_TITLE: &'static str = "La bonega karelizisto";
     const WINDOW_SIZE: (u32, u32) = (800, 600);
     const MAX_FPS: u32 = 60;
 
@@ -51,16 +87,27 @@ pub fn start_editor<P>(schema: Schema, level: Level, save_to: Option<P>)
 
     let device = Device::new(renderer);
     let renderer = device.create_renderer();
+    let path = font_path(&schema, &settings).ok_or_else(no_font)?;
+    let font = font_context.load_font(&path, FONT_SIZE)
+        .map_err(|err| format!("could not load font {:?}: {}", path, err))?;
     let resources = ResourceManager::with_prefix(schema.prefix, &device, &font_context);
 
     // Load units
 
     // Set up game state.
 
-    let mut state = State::new(resources);
+    let mut state = State::new(resources, font);
 
     // Prepare the scene
     let mut editor = Editor::new(schema.layers, schema.tiles, schema.colors, level, save_to);
+    editor.set_history_depth(settings.history_depth);
+    editor.set_backups(settings.backups);
+    if let Some(swap) = settings.color_swap {
+        editor.set_color_swap(Some(swap));
+    }
+    if recovered {
+        editor.mark_dirty();
+    }
 
     // Set up input handling.
 
@@ -68,26 +115,114 @@ pub fn start_editor<P>(schema: Schema, level: Level, save_to: Option<P>)
 
     mapper.add(map_event!(Quit { .. }, PreExit));
 
-    mapper.add(map_scan_pressed!(Scancode::Up, Up));
-    mapper.add(map_scan_pressed!(Scancode::Left, Left));
-    mapper.add(map_scan_pressed!(Scancode::Down, Down));
-    mapper.add(map_scan_pressed!(Scancode::Right, Right));
+    macro_rules! map_scroll {
+        ($scancode:path, $dx:expr, $dy:expr) => {
+            mapper.add(map_event!(
+                KeyDown { scancode: Some($scancode), repeat: false, .. },
+                ScrollStart($dx, $dy)
+            ));
+            mapper.add(map_event!(
+                KeyUp { scancode: Some($scancode), .. },
+                ScrollStop($dx, $dy)
+            ));
+        }
+    }
+
+    map_scroll!(Scancode::Up, 0, 1);
+    map_scroll!(Scancode::Left, -1, 0);
+    map_scroll!(Scancode::Down, 0, -1);
+    map_scroll!(Scancode::Right, 1, 0);
 
-    mapper.add(map_scan_pressed!(Scancode::W, Up));
-    mapper.add(map_scan_pressed!(Scancode::A, Left));
-    mapper.add(map_scan_pressed!(Scancode::S, Down));
-    mapper.add(map_scan_pressed!(Scancode::D, Right));
+    map_scroll!(Scancode::W, 0, 1);
+    map_scroll!(Scancode::A, -1, 0);
+    map_scroll!(Scancode::S, 0, -1);
+    map_scroll!(Scancode::D, 1, 0);
 
-    mapper.add(map_scan_pressed!(Scancode::Z, PrevLayer));
-    mapper.add(map_scan_pressed!(Scancode::X, NextLayer));
+    mapper.add(map_event!(
+        KeyDown { scancode: Some(Scancode::Space), repeat: false, .. },
+        PanKey(true)
+    ));
+    mapper.add(map_event!(KeyUp { scancode: Some(Scancode::Space), .. }, PanKey(false)));
+
+    mapper.add(map_event!(
+        KeyDown { scancode: Some(Scancode::Z), keymod, .. },
+        match (ctrl(keymod), shift(keymod)) {
+            (true, false) => Undo,
+            (true, true) => Redo,
+            _ => PrevLayer,
+        }
+    ));
+    mapper.add(map_event!(
+        KeyDown { scancode: Some(Scancode::X), keymod, .. },
+        if ctrl(keymod) { Cut } else { NextLayer }
+    ));
     mapper.add(map_scan_pressed!(Scancode::Q, PrevTile));
     mapper.add(map_scan_pressed!(Scancode::E, NextTile));
 
     mapper.add(map_scan_pressed!(Scancode::Num1, PrevColor));
     mapper.add(map_scan_pressed!(Scancode::Num2, NextColor));
+    mapper.add(map_event!(
+        KeyDown { scancode: Some(Scancode::C), keymod, .. },
+        if ctrl(keymod) { Copy } else { PrevColor }
+    ));
+    mapper.add(map_event!(
+        KeyDown { scancode: Some(Scancode::V), keymod, .. },
+        if ctrl(keymod) { Paste } else { NextColor }
+    ));
 
     mapper.add(map_scan_pressed!(Scancode::Return, Save));
 
+    mapper.add(map_scan_pressed!(Scancode::P, SetTool(Tool::Paint)));
+    mapper.add(map_scan_pressed!(Scancode::R, SetTool(Tool::Rect)));
+    mapper.add(map_scan_pressed!(Scancode::F, SetTool(Tool::Fill)));
+    mapper.add(map_scan_pressed!(Scancode::L, SetTool(Tool::Line)));
+    mapper.add(map_scan_pressed!(Scancode::M, SetTool(Tool::Select)));
+
+    mapper.add(map_scan_pressed!(Scancode::Delete, Delete));
+    mapper.add(map_scan_pressed!(Scancode::Backspace, Delete));
+    mapper.add(map_scan_pressed!(Scancode::Escape, Deselect));
+    mapper.add(map_scan_pressed!(Scancode::T, ToggleAllLayers));
+
+    mapper.add(map_scan_pressed!(Scancode::Kp8, ShiftLevel(0, 1)));
+    mapper.add(map_scan_pressed!(Scancode::Kp4, ShiftLevel(-1, 0)));
+    mapper.add(map_scan_pressed!(Scancode::Kp2, ShiftLevel(0, -1)));
+    mapper.add(map_scan_pressed!(Scancode::Kp6, ShiftLevel(1, 0)));
+    mapper.add(map_scan_pressed!(Scancode::Kp5, NormalizeLevel));
+
+    mapper.add(map_event!(
+        KeyDown { scancode: Some(Scancode::B), keymod, .. },
+        if shift(keymod) { SwapPaletteDock } else { TogglePalette }
+    ));
+
+    mapper.add(map_event!(
+        KeyDown { scancode: Some(Scancode::H), keymod, .. },
+        if shift(keymod) {
+            TransformSelection(Transform::FlipVertical)
+        } else {
+            TransformSelection(Transform::FlipHorizontal)
+        }
+    ));
+    mapper.add(map_event!(
+        KeyDown { scancode: Some(Scancode::O), keymod, .. },
+        if shift(keymod) {
+            TransformSelection(Transform::Rotate180)
+        } else {
+            TransformSelection(Transform::Rotate90)
+        }
+    ));
+    mapper.add(map_scan_pressed!(Scancode::N, ToggleColorSwap));
+
+    mapper.add(map_scan_pressed!(Scancode::Y, NextSymmetry));
+    mapper.add(map_scan_pressed!(Scancode::U, SetSymmetryCenter));
+    mapper.add(map_scan_pressed!(Scancode::G, ToggleColorRotation));
+    mapper.add(map_scan_pressed!(Scancode::I, Pick));
+
+    mapper.add(map_scan_pressed!(Scancode::Equals, Zoom(1)));
+    mapper.add(map_scan_pressed!(Scancode::KpPlus, Zoom(1)));
+    mapper.add(map_scan_pressed!(Scancode::Minus, Zoom(-1)));
+    mapper.add(map_scan_pressed!(Scancode::KpMinus, Zoom(-1)));
+    mapper.add(map_event!(MouseWheel { y, .. }, Zoom(y)));
+
     mapper.add(map_event!(
          MouseButtonDown { x, y, mouse_btn: Mouse::Left, .. },
          LeftClickAt((x * pw as i32) / w as i32, (y * ph as i32) / h as i32)
@@ -104,6 +239,14 @@ pub fn start_editor<P>(schema: Schema, level: Level, save_to: Option<P>)
          MouseButtonUp { x, y, mouse_btn: Mouse::Right, .. },
          RightReleasedAt((x * pw as i32) / w as i32, (y * ph as i32) / h as i32)
     ));
+    mapper.add(map_event!(
+         MouseButtonDown { x, y, mouse_btn: Mouse::Middle, .. },
+         MiddleClickAt((x * pw as i32) / w as i32, (y * ph as i32) / h as i32)
+    ));
+    mapper.add(map_event!(
+         MouseButtonUp { x, y, mouse_btn: Mouse::Middle, .. },
+         MiddleReleasedAt((x * pw as i32) / w as i32, (y * ph as i32) / h as i32)
+    ));
     mapper.add(map_event!(
         MouseMotion { x, y, .. },
         MouseMovedTo((x * pw as i32) / w as i32, (y * ph as i32) / h as i32)
@@ -115,4 +258,5 @@ pub fn start_editor<P>(schema: Schema, level: Level, save_to: Option<P>)
     let mut game = Game::new(MAX_FPS, renderer, event_pump);
 
     game.run(&mut state, &mapper, &mut editor, |m| *m == Exit);
+    Ok(())
 }
@@ -1,57 +1,817 @@
-use std::cmp::{Ord, Ordering, PartialOrd};
-use std::collections::{BTreeSet, HashMap};
-use std::fs::File;
-use std::path::Path;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, hash_map};
+use std::ffi::OsString;
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::i32;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use json;
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
-pub struct Point(pub i32, pub i32, pub u32);
+use asciimap;
 
-impl PartialEq for Point {
+/// The extension of levels in the text format.
+pub const TEXT_EXTENSION: &'static str = "txt";
+
+/// Returns whether the level at a path is in the text format rather than JSON.
+pub fn is_text_path(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == TEXT_EXTENSION)
+}
+
+/// An error from reading or writing a level file.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(json::Error),
+    /// A level in the text format could not be read or written.
+    Text(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::Json(ref err) => write!(f, "{}", err),
+            Error::Text(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    #[inline]
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<json::Error> for Error {
+    #[inline]
+    fn from(err: json::Error) -> Error {
+        Error::Json(err)
+    }
+}
+
+/// The position of a cell in a level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pos(pub i32, pub i32);
+
+impl From<(i32, i32)> for Pos {
+    #[inline]
+    fn from(pos: (i32, i32)) -> Pos {
+        Pos(pos.0, pos.1)
+    }
+}
+
+/// A tile placed in a cell.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Tile {
+    /// The name of the tile in the schema.
+    pub name: String,
+    pub color: u32,
+}
+
+impl Tile {
     #[inline]
-    fn eq(&self, other: &Point) -> bool {
-        (self.0, self.1) == (other.0, other.1)
+    pub fn new<S>(name: S, color: u32) -> Tile
+        where S: Into<String>
+    {
+        Tile {
+            name: name.into(),
+            color: color,
+        }
     }
 }
 
-impl Eq for Point {}
+/// The width and height of a chunk is `1 << CHUNK_BITS` cells.
+const CHUNK_BITS: i32 = 4;
+const CHUNK_SIZE: i32 = 1 << CHUNK_BITS;
+const CHUNK_MASK: i32 = CHUNK_SIZE - 1;
+
+/// Returns the chunk containing a position, and the index of the cell within it.
+#[inline]
+fn locate(pos: Pos) -> ((i32, i32), usize) {
+    // Shifting and masking rounds towards negative infinity, unlike division.
+    let key = (pos.0 >> CHUNK_BITS, pos.1 >> CHUNK_BITS);
+    let index = ((pos.1 & CHUNK_MASK) * CHUNK_SIZE + (pos.0 & CHUNK_MASK)) as usize;
+    (key, index)
+}
+
+/// A square of cells of a layer.
+#[derive(Clone, Debug, PartialEq)]
+struct Chunk {
+    cells: Vec<Option<Tile>>,
+    /// The number of occupied cells.
+    len: usize,
+}
 
-impl PartialOrd for Point {
+impl Chunk {
     #[inline]
-    fn partial_cmp(&self, other: &Point) -> Option<Ordering> {
-        Some(self.cmp(other))
+    fn new() -> Chunk {
+        Chunk {
+            cells: vec![None; (CHUNK_SIZE * CHUNK_SIZE) as usize],
+            len: 0,
+        }
     }
 }
 
-impl Ord for Point {
+/// The tiles of a layer, with at most one tile in each cell.
+///
+/// The cells are kept in square chunks, so that looking up a cell takes constant time,
+/// and the tiles in an area can be found without looking at the rest of the layer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Layer {
+    chunks: HashMap<(i32, i32), Chunk>,
+    len: usize,
+}
+
+impl Layer {
+    #[inline]
+    pub fn new() -> Layer {
+        Layer {
+            chunks: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the tile at the given position.
     #[inline]
-    fn cmp(&self, other: &Point) -> Ordering {
-        (self.0, self.1).cmp(&(other.0, other.1))
+    pub fn get<P>(&self, pos: P) -> Option<&Tile>
+        where P: Into<Pos>
+    {
+        let (key, index) = locate(pos.into());
+        self.chunks.get(&key).and_then(|chunk| chunk.cells[index].as_ref())
+    }
+
+    /// Places a tile, returning the one it replaced.
+    pub fn set<P>(&mut self, pos: P, tile: Tile) -> Option<Tile>
+        where P: Into<Pos>
+    {
+        let (key, index) = locate(pos.into());
+        let chunk = self.chunks.entry(key).or_insert_with(Chunk::new);
+        let before = chunk.cells[index].take();
+        chunk.cells[index] = Some(tile);
+        if before.is_none() {
+            chunk.len += 1;
+            self.len += 1;
+        }
+        before
+    }
+
+    /// Removes the tile at the given position, if any.
+    pub fn remove<P>(&mut self, pos: P) -> Option<Tile>
+        where P: Into<Pos>
+    {
+        let (key, index) = locate(pos.into());
+        let (before, empty) = match self.chunks.get_mut(&key) {
+            Some(chunk) => {
+                let before = chunk.cells[index].take();
+                if before.is_some() {
+                    chunk.len -= 1;
+                }
+                (before, chunk.len == 0)
+            }
+            None => return None,
+        };
+        if before.is_some() {
+            self.len -= 1;
+        }
+        if empty {
+            self.chunks.remove(&key);
+        }
+        before
+    }
+
+    /// Returns an iterator over every tile, in no particular order.
+    #[inline]
+    pub fn iter(&self) -> Iter {
+        Iter {
+            chunks: self.chunks.iter(),
+            chunk: None,
+            index: 0,
+        }
+    }
+
+    /// Returns an iterator over the tiles inside the inclusive span `(x, y, x', y')`.
+    pub fn iter_span(&self, span: (i32, i32, i32, i32)) -> SpanIter {
+        let (x1, y1, x2, y2) = span;
+        let chunks = (x1 >> CHUNK_BITS, y1 >> CHUNK_BITS, x2 >> CHUNK_BITS, y2 >> CHUNK_BITS);
+        SpanIter {
+            layer: self,
+            span: span,
+            chunks: chunks,
+            // An empty span has no chunks to visit.
+            next_chunk: if x1 <= x2 && y1 <= y2 {
+                (chunks.0, chunks.1)
+            } else {
+                (chunks.0, chunks.3 + 1)
+            },
+            chunk: None,
+            cells: (0, 0, -1, -1),
+            cell: (0, 0),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the inclusive bounds `(x, y, x', y')` of every tile in the layer, or `None`
+    /// if it is empty.
+    pub fn occupied_bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        let mut bounds = None;
+        for (pos, _) in self {
+            let (x1, y1, x2, y2) = bounds.unwrap_or((pos.0, pos.1, pos.0, pos.1));
+            bounds = Some((cmp::min(x1, pos.0),
+                           cmp::min(y1, pos.1),
+                           cmp::max(x2, pos.0),
+                           cmp::max(y2, pos.1)));
+        }
+        bounds
+    }
+
+    /// Moves every tile by `delta`.
+    pub fn translate(&mut self, delta: (i32, i32)) {
+        if delta == (0, 0) {
+            return;
+        }
+        let old = mem::replace(self, Layer::new());
+        for (pos, tile) in &old {
+            self.set((pos.0 + delta.0, pos.1 + delta.1), tile.clone());
+        }
+    }
+
+    /// Removes every tile outside the inclusive span `(x, y, x', y')`, returning how many
+    /// were removed.
+    pub fn crop(&mut self, span: (i32, i32, i32, i32)) -> usize {
+        let (x1, y1, x2, y2) = span;
+        let outside = self.iter()
+            .map(|(pos, _)| pos)
+            .filter(|pos| pos.0 < x1 || x2 < pos.0 || pos.1 < y1 || y2 < pos.1)
+            .collect::<Vec<_>>();
+        for &pos in &outside {
+            self.remove(pos);
+        }
+        outside.len()
+    }
+}
+
+impl<'a> IntoIterator for &'a Layer {
+    type Item = (Pos, &'a Tile);
+    type IntoIter = Iter<'a>;
+
+    #[inline]
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// An iterator over every tile of a layer.
+pub struct Iter<'a> {
+    chunks: hash_map::Iter<'a, (i32, i32), Chunk>,
+    chunk: Option<(&'a (i32, i32), &'a Chunk)>,
+    index: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (Pos, &'a Tile);
+
+    fn next(&mut self) -> Option<(Pos, &'a Tile)> {
+        loop {
+            if let Some((key, chunk)) = self.chunk {
+                while self.index < chunk.cells.len() {
+                    let index = self.index;
+                    self.index += 1;
+                    if let Some(ref tile) = chunk.cells[index] {
+                        let pos = Pos((key.0 << CHUNK_BITS) + index as i32 % CHUNK_SIZE,
+                                      (key.1 << CHUNK_BITS) + index as i32 / CHUNK_SIZE);
+                        return Some((pos, tile));
+                    }
+                }
+            }
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.chunk = Some(chunk);
+                    self.index = 0;
+                }
+                None => return None,
+            }
+        }
+    }
+}
+
+/// An iterator over the tiles of a layer inside a span, visiting only the chunks that
+/// overlap it.
+pub struct SpanIter<'a> {
+    layer: &'a Layer,
+    span: (i32, i32, i32, i32),
+    /// The inclusive span of chunks to visit.
+    chunks: (i32, i32, i32, i32),
+    next_chunk: (i32, i32),
+    chunk: Option<&'a Chunk>,
+    /// The inclusive span of cells to visit in the current chunk.
+    cells: (i32, i32, i32, i32),
+    cell: (i32, i32),
+}
+
+impl<'a> Iterator for SpanIter<'a> {
+    type Item = (Pos, &'a Tile);
+
+    fn next(&mut self) -> Option<(Pos, &'a Tile)> {
+        loop {
+            if let Some(chunk) = self.chunk {
+                let (x1, _, x2, y2) = self.cells;
+                while self.cell.1 <= y2 {
+                    let pos = Pos(self.cell.0, self.cell.1);
+                    if self.cell.0 < x2 {
+                        self.cell.0 += 1;
+                    } else {
+                        self.cell = (x1, self.cell.1 + 1);
+                    }
+                    if let Some(ref tile) = chunk.cells[locate(pos).1] {
+                        return Some((pos, tile));
+                    }
+                }
+                self.chunk = None;
+            }
+
+            let (cx1, _, cx2, cy2) = self.chunks;
+            let key = self.next_chunk;
+            if key.1 > cy2 {
+                return None;
+            }
+            self.next_chunk = if key.0 < cx2 {
+                (key.0 + 1, key.1)
+            } else {
+                (cx1, key.1 + 1)
+            };
+            if let Some(chunk) = self.layer.chunks.get(&key) {
+                let (x1, y1, x2, y2) = self.span;
+                let (left, bottom) = (key.0 << CHUNK_BITS, key.1 << CHUNK_BITS);
+                self.cells = (cmp::max(x1, left),
+                              cmp::max(y1, bottom),
+                              cmp::min(x2, left + CHUNK_MASK),
+                              cmp::min(y2, bottom + CHUNK_MASK));
+                self.cell = (self.cells.0, self.cells.1);
+                self.chunk = Some(chunk);
+            }
+        }
+    }
+}
+
+/// Checks that a level size can be used, which means that every cell within it has an
+/// `i32` position.
+pub fn check_size(size: (u32, u32)) -> Result<(), String> {
+    if size.0 > i32::MAX as u32 || size.1 > i32::MAX as u32 {
+        return Err(format!("the level size {}x{} is too large (at most {} each)",
+                           size.0,
+                           size.1,
+                           i32::MAX));
     }
+    Ok(())
+}
+
+/// A level as written on disk, with the points of each tile listed as `[x, y, color]`.
+///
+/// Unlike a `Level`, this can hold several tiles in the same cell.
+#[derive(Deserialize)]
+pub struct RawLevel {
+    pub name: String,
+    pub schema: String,
+    pub size: Option<(u32, u32)>,
+    pub layers: HashMap<String, HashMap<String, Vec<(i32, i32, u32)>>>,
 }
 
-pub type Layer = HashMap<String, BTreeSet<Point>>;
+impl RawLevel {
+    /// Reads a level file, in the text format if the path has the text extension.
+    pub fn load<P>(path: P) -> Result<RawLevel, Error>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        if is_text_path(path) {
+            let mut contents = String::new();
+            BufReader::new(file).read_to_string(&mut contents)?;
+            asciimap::parse(&contents)
+        } else {
+            let raw: RawLevel = json::from_reader(file)?;
+            if let Some(size) = raw.size {
+                check_size(size).map_err(Error::Text)?;
+            }
+            Ok(raw)
+        }
+    }
+}
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Where the old content of a level ends up when it is resized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Returns the horizontal and vertical placement, each from -1 (left or bottom) to 1.
+    fn sides(self) -> (i32, i32) {
+        use self::Anchor::*;
+        match self {
+            TopLeft => (-1, 1),
+            Top => (0, 1),
+            TopRight => (1, 1),
+            Left => (-1, 0),
+            Center => (0, 0),
+            Right => (1, 0),
+            BottomLeft => (-1, -1),
+            Bottom => (0, -1),
+            BottomRight => (1, -1),
+        }
+    }
+}
+
+impl FromStr for Anchor {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Anchor, String> {
+        use self::Anchor::*;
+        Ok(match text {
+            "top-left" => TopLeft,
+            "top" => Top,
+            "top-right" => TopRight,
+            "left" => Left,
+            "center" => Center,
+            "right" => Right,
+            "bottom-left" => BottomLeft,
+            "bottom" => Bottom,
+            "bottom-right" => BottomRight,
+            _ => return Err(format!("unknown anchor {:?}", text)),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Level {
     pub name: String,
     pub schema: String,
+    /// The width and height of the playable area, which starts at `(0, 0)`, or `None` if
+    /// the level is unbounded.
+    pub size: Option<(u32, u32)>,
     pub layers: HashMap<String, Layer>,
 }
 
 impl Level {
+    /// Creates an empty level.
+    #[inline]
+    pub fn new<S, T>(name: S, schema: T) -> Level
+        where S: Into<String>,
+              T: Into<String>
+    {
+        Level {
+            name: name.into(),
+            schema: schema.into(),
+            size: None,
+            layers: HashMap::new(),
+        }
+    }
+
+    /// Converts a level read from disk.
+    ///
+    /// If several tiles share a cell, the tile with the first name in sorted order wins,
+    /// which is the one that validation reports the others as conflicting with.
+    pub fn from_raw(raw: RawLevel) -> Level {
+        let mut layers = HashMap::new();
+        for (layer_name, raw_layer) in raw.layers {
+            let mut tiles = raw_layer.into_iter().collect::<Vec<_>>();
+            tiles.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut layer = Layer::new();
+            for (name, points) in tiles {
+                for (x, y, color) in points {
+                    if layer.get((x, y)).is_none() {
+                        layer.set((x, y), Tile::new(name.clone(), color));
+                    }
+                }
+            }
+            layers.insert(layer_name, layer);
+        }
+        Level {
+            name: raw.name,
+            schema: raw.schema,
+            size: raw.size,
+            layers: layers,
+        }
+    }
+
+    /// Returns the playable area as an inclusive span `(x, y, x', y')`, if the level is
+    /// bounded.
+    #[inline]
+    pub fn bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        self.size.map(|(w, h)| (0, 0, w as i32 - 1, h as i32 - 1))
+    }
+
+    /// Returns whether a cell lies within the playable area.
+    #[inline]
+    pub fn contains(&self, pos: (i32, i32)) -> bool {
+        self.bounds().map_or(true, |(x1, y1, x2, y2)| {
+            x1 <= pos.0 && pos.0 <= x2 && y1 <= pos.1 && pos.1 <= y2
+        })
+    }
+
+    /// Changes the size of the level, moving the content so that it keeps its place
+    /// relative to the anchor, and removing the tiles that end up outside.
+    ///
+    /// An unbounded level is treated as being just large enough for the tiles that do not
+    /// lie below or to the left of `(0, 0)`. Returns how many tiles were removed.
+    pub fn resize(&mut self, size: (u32, u32), anchor: Anchor) -> usize {
+        let old = match self.size {
+            Some(old) => old,
+            None => {
+                self.occupied_bounds()
+                    .map_or((0, 0), |(_, _, x2, y2)| {
+                        (cmp::max(x2 + 1, 0) as u32, cmp::max(y2 + 1, 0) as u32)
+                    })
+            }
+        };
+        let (sx, sy) = anchor.sides();
+        let offset = |side: i32, old: u32, new: u32| {
+            let extra = new as i32 - old as i32;
+            match side {
+                -1 => 0,
+                0 => extra / 2,
+                _ => extra,
+            }
+        };
+        let delta = (offset(sx, old.0, size.0), offset(sy, old.1, size.1));
+        self.size = Some(size);
+        let span = (0, 0, size.0 as i32 - 1, size.1 as i32 - 1);
+        let mut removed = 0;
+        for layer in self.layers.values_mut() {
+            layer.translate(delta);
+            removed += layer.crop(span);
+        }
+        removed
+    }
+
+    /// Returns the inclusive bounds `(x, y, x', y')` of every tile in the level, or `None`
+    /// if it is empty.
+    pub fn occupied_bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        self.layers.values().fold(None, |bounds, layer| {
+            match (bounds, layer.occupied_bounds()) {
+                (Some((x1, y1, x2, y2)), Some((u1, v1, u2, v2))) => {
+                    Some((cmp::min(x1, u1), cmp::min(y1, v1), cmp::max(x2, u2), cmp::max(y2, v2)))
+                }
+                (bounds, None) => bounds,
+                (None, other) => other,
+            }
+        })
+    }
+
+    /// Moves the tiles of the named layer, or of every layer if `layer` is `None`, by
+    /// `delta`.
+    ///
+    /// Tiles keep their place even if they end up outside the bounds of the level.
+    pub fn shift(&mut self, delta: (i32, i32), layer: Option<&str>) {
+        for (name, tiles) in &mut self.layers {
+            if layer.map_or(true, |layer| layer == name) {
+                tiles.translate(delta);
+            }
+        }
+    }
+
+    /// Returns the shift that moves the corner with the smallest coordinates of the area
+    /// occupied by the named layer, or by every layer if `layer` is `None`, to `(0, 0)`.
+    ///
+    /// Since y points up, this is the bottom-left corner as seen in the editor, and the
+    /// occupied area then starts at the corner of the level bounds.
+    pub fn normalizing_shift(&self, layer: Option<&str>) -> (i32, i32) {
+        let bounds = match layer {
+            Some(layer) => self.layers.get(layer).and_then(|layer| layer.occupied_bounds()),
+            None => self.occupied_bounds(),
+        };
+        bounds.map_or((0, 0), |(x1, y1, _, _)| (-x1, -y1))
+    }
+
+    /// Loads a level, in the text format if the path has the text extension.
     #[inline]
-    pub fn load<P>(path: P) -> Result<Level, json::Error>
+    pub fn load<P>(path: P) -> Result<Level, Error>
         where P: AsRef<Path>
     {
-        json::from_reader(File::open(path)?)
+        Ok(Level::from_raw(RawLevel::load(path)?))
     }
 
+    /// Writes the level in the text format.
     #[inline]
-    pub fn save<P>(&self, path: P) -> Result<(), json::Error>
+    pub fn write_text<W>(&self, writer: &mut W) -> Result<(), Error>
+        where W: Write
+    {
+        asciimap::write(self, writer)
+    }
+
+    /// Saves the level in its canonical form, without keeping backups.
+    #[inline]
+    pub fn save<P>(&self, path: P) -> Result<(), Error>
+        where P: AsRef<Path>
+    {
+        self.save_with_backups(path, 0)
+    }
+
+    /// Saves the level in its canonical form, keeping up to `backups` previous versions.
+    ///
+    /// Paths with the text extension get the text format, and other paths get JSON. The
+    /// level is first written to a temporary file in the same directory, which then
+    /// replaces the old file, so that a failed save leaves the old file intact. The
+    /// previous versions are kept as `<path>.1.bak` (the newest) to `<path>.<N>.bak`.
+    pub fn save_with_backups<P>(&self, path: P, backups: usize) -> Result<(), Error>
         where P: AsRef<Path>
     {
-        json::to_writer(&mut File::create(path)?, self)
+        let path = path.as_ref();
+        let tmp_path = sibling_path(path, ".tmp");
+        let result = self.write_file(&tmp_path, is_text_path(path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+            return result;
+        }
+        if backups != 0 && path.exists() {
+            for i in (1..backups).rev() {
+                let older = backup_path(path, i);
+                if older.exists() {
+                    fs::rename(&older, backup_path(path, i + 1))?;
+                }
+            }
+            fs::copy(path, backup_path(path, 1))?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    fn write_file(&self, path: &Path, text: bool) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        if text {
+            self.write_text(&mut writer)?;
+        } else {
+            self.write_canonical(&mut writer)?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    /// Writes the level as pretty-printed JSON with layers, tiles and points in sorted
+    /// order, so that saving the same level always gives the same output.
+    ///
+    /// Empty layers and tiles are left out, and every point is on a line of its own.
+    pub fn write_canonical<W>(&self, writer: &mut W) -> Result<(), json::Error>
+        where W: Write
+    {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "    \"name\": {},", json::to_string(&self.name)?)?;
+        writeln!(writer, "    \"schema\": {},", json::to_string(&self.schema)?)?;
+        if let Some((w, h)) = self.size {
+            writeln!(writer, "    \"size\": [{}, {}],", w, h)?;
+        }
+        write!(writer, "    \"layers\": {{")?;
+
+        let mut layers = self.layers
+            .iter()
+            .filter(|&(_, layer)| !layer.is_empty())
+            .collect::<Vec<_>>();
+        layers.sort_by(|a, b| a.0.cmp(b.0));
+        for (i, &(layer_name, layer)) in layers.iter().enumerate() {
+            write_separator(writer, i)?;
+            write!(writer, "        {}: {{", json::to_string(layer_name)?)?;
+
+            let mut tiles = BTreeMap::new();
+            for (pos, tile) in layer {
+                tiles.entry(&tile.name[..]).or_insert_with(Vec::new).push((pos, tile.color));
+            }
+            for points in tiles.values_mut() {
+                points.sort();
+            }
+            for (j, (name, points)) in tiles.into_iter().enumerate() {
+                write_separator(writer, j)?;
+                write!(writer, "            {}: [", json::to_string(&name)?)?;
+                for (k, &(pos, color)) in points.iter().enumerate() {
+                    write_separator(writer, k)?;
+                    write!(writer, "                [{}, {}, {}]", pos.0, pos.1, color)?;
+                }
+                write!(writer, "\n            ]")?;
+            }
+            write!(writer, "\n        }}")?;
+        }
+        if layers.is_empty() {
+            writeln!(writer, "}}")?;
+        } else {
+            writeln!(writer, "\n    }}")?;
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+}
+
+/// Returns the path with a suffix added to its file name.
+pub fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_else(OsString::new);
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Returns the path of the `n`th newest backup of a file.
+#[inline]
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    sibling_path(path, &format!(".{}.bak", n))
+}
+
+/// Ends the previous entry of a JSON list or object, if any, and starts a new line.
+#[inline]
+fn write_separator<W>(writer: &mut W, index: usize) -> io::Result<()>
+    where W: Write
+{
+    if index == 0 {
+        writeln!(writer, "")
+    } else {
+        writeln!(writer, ",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Layer, Pos, Tile, locate};
+
+    /// Returns a layer with tiles scattered over several chunks on both sides of zero.
+    fn scattered() -> Layer {
+        let mut layer = Layer::new();
+        for x in -40..40 {
+            for y in -40..40 {
+                if (x * 7 + y * 3) % 5 == 0 {
+                    layer.set((x, y), Tile::new("wall", ((x + y) & 3) as u32));
+                }
+            }
+        }
+        layer
+    }
+
+    fn sorted<'a, I>(tiles: I) -> Vec<(Pos, Tile)>
+        where I: Iterator<Item = (Pos, &'a Tile)>
+    {
+        let mut tiles = tiles.map(|(pos, tile)| (pos, tile.clone())).collect::<Vec<_>>();
+        tiles.sort_by(|a, b| a.0.cmp(&b.0));
+        tiles
+    }
+
+    #[test]
+    fn locate_rounds_towards_negative_infinity() {
+        assert_eq!(locate(Pos(0, 0)), ((0, 0), 0));
+        assert_eq!(locate(Pos(15, 16)), ((0, 1), 15));
+        assert_eq!(locate(Pos(-1, -1)), ((-1, -1), 255));
+        assert_eq!(locate(Pos(-16, -17)), ((-1, -2), 240));
+    }
+
+    #[test]
+    fn cells_on_both_sides_of_chunk_edges_are_distinct() {
+        let mut layer = Layer::new();
+        let positions =
+            [(-17, 0), (-16, 0), (-1, -1), (0, -1), (-1, 0), (0, 0), (15, 16), (16, 15)];
+        for (i, &pos) in positions.iter().enumerate() {
+            assert_eq!(layer.set(pos, Tile::new("wall", i as u32)), None);
+        }
+        for (i, &pos) in positions.iter().enumerate() {
+            assert_eq!(layer.get(pos), Some(&Tile::new("wall", i as u32)));
+        }
+        assert_eq!(layer.iter().count(), positions.len());
+        assert_eq!(layer.remove((-1, -1)), Some(Tile::new("wall", 2)));
+        assert_eq!(layer.get((-1, -1)), None);
+        assert_eq!(layer.iter().count(), positions.len() - 1);
+    }
+
+    #[test]
+    fn iter_span_matches_filtered_iter() {
+        let layer = scattered();
+        let spans = [(-40, -40, 39, 39),
+                     (-17, -17, 16, 16),
+                     (-16, -1, -1, 15),
+                     (-3, -20, 2, -14),
+                     (5, 5, 5, 5),
+                     (-33, 7, -31, 40),
+                     (100, 100, 120, 120)];
+        for &(x1, y1, x2, y2) in &spans {
+            let expected = sorted(layer.iter()
+                .filter(|&(pos, _)| x1 <= pos.0 && pos.0 <= x2 && y1 <= pos.1 && pos.1 <= y2));
+            assert_eq!(sorted(layer.iter_span((x1, y1, x2, y2))), expected);
+        }
+    }
+
+    #[test]
+    fn iter_span_of_empty_span() {
+        let layer = scattered();
+        assert_eq!(layer.iter_span((0, 0, -1, 10)).count(), 0);
+        assert_eq!(layer.iter_span((0, 0, 10, -1)).count(), 0);
     }
 }
@@ -45,13 +45,15 @@ fn warn_about_unused_keys<F>(value: toml::Value, path: &mut String, warn: &mut F
     result
 }
 
-fn make_context(err: &toml::ParserError, contents: &str, ctx: &mut String) {
-    let i = contents[..err.lo + 1].rfind('\n').unwrap_or(!0).wrapping_add(1);
-    let j = err.lo + contents[err.lo..].find('\n').unwrap_or(contents.len() - err.lo);
-    let line_num = contents[..i].split('\n').count() + 1;
+/// Appends the line containing the byte range `lo..hi` of `contents` to `ctx`, with the
+/// range marked below it.
+pub fn make_context(contents: &str, lo: usize, hi: usize, ctx: &mut String) {
+    let i = contents[..lo].rfind('\n').map_or(0, |i| i + 1);
+    let j = lo + contents[lo..].find('\n').unwrap_or(contents.len() - lo);
+    let line_num = contents[..i].matches('\n').count() + 1;
     let line = &contents[i..j];
 
-    let trailing = if j < err.hi {
+    let trailing = if j < hi {
         "..."
     } else {
         ""
@@ -60,10 +62,10 @@ fn make_context(err: &toml::ParserError, contents: &str, ctx: &mut String) {
     write!(ctx, "{} > ", line_num).unwrap();
     let prefix_len = ctx.len() - prev_len;
     write!(ctx, "{}{}\n", line, trailing).unwrap();
-    for _ in 0..(prefix_len + err.lo - i) {
+    for _ in 0..(prefix_len + lo - i) {
         ctx.push(' ');
     }
-    for _ in 0..cmp::max(cmp::min(err.hi - err.lo, j - err.lo), 1) {
+    for _ in 0..cmp::max(cmp::min(hi - lo, j - lo), 1) {
         ctx.push('~');
     }
 }
@@ -82,7 +84,7 @@ pub fn load_toml<T, P, F>(path: P, mut warn: F) -> Result<T, Error>
     let table = parser.parse();
     for warning in &parser.errors {
         let mut msg = format!("parsing file: {}\n", warning);
-        make_context(warning, &contents, &mut msg);
+        make_context(&contents, warning.lo, warning.hi, &mut msg);
         warn(&msg);
     }
     let table = table.ok_or(Error::Parse)?;
@@ -10,6 +10,7 @@ extern crate log;
 extern crate serde;
 extern crate serde_json as json;
 extern crate toml;
+extern crate xml;
 
 extern crate argonaut;
 #[macro_use]
@@ -19,20 +20,36 @@ extern crate sdl2_image;
 extern crate sdl2_ttf;
 
 use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
+use common::Settings;
+use editor::autosave_path;
 use info::Schema;
-use launch::start_editor;
+use launch::{font_path, no_font, start_editor};
 use level::Level;
 use load::load_toml;
+use parse::Command;
 
+mod asciimap;
+mod clipboard;
 mod common;
 mod editor;
+mod export;
 mod info;
 mod launch;
 mod level;
 mod load;
+mod palette;
+mod parse;
 mod spec;
+mod symmetry;
+mod text;
+mod tiled;
 mod toolbox;
+mod validate;
 
 fn main() {
     // Set up logging.
@@ -55,12 +72,199 @@ fn main() {
 
     // Main
 
-    let schema = match load_toml("schema.toml", |m| warn!("{}", m)) {
-        Ok(spec) => Schema::from_spec(spec).expect("could not validate schema"),
-        Err(err) => panic!("could not load schema: {}", err),
+    let command = match parse::parse() {
+        Ok(Some(command)) => command,
+        Ok(None) => return,
+        Err(msg) => {
+            let _ = writeln!(io::stderr(), "error: {}\n\n{}", msg, parse::USAGE);
+            process::exit(2);
+        }
     };
-    let level = Level::load("level.json").unwrap();
-    assert!(level.schema == schema.name);
+    if let Err(msg) = run(command) {
+        let _ = writeln!(io::stderr(), "error: {}", msg);
+        process::exit(1);
+    }
+}
+
+fn load_schema(path: &Path) -> Result<Schema, String> {
+    let spec = load_toml(path, |m| warn!("{}", m))
+        .map_err(|err| format!("could not load schema {:?}: {}", path, err))?;
+    Schema::from_spec(spec).map_err(|err| format!("invalid schema {:?}: {}", path, err))
+}
+
+fn load_level(path: &Path, schema: &Schema) -> Result<Level, String> {
+    let problems = validate::validate_file(path, schema)
+        .map_err(|err| format!("could not load level {:?}: {}", path, err))?;
+    let mut fatal = 0;
+    for problem in problems.iter().filter(|p| p.prevents_editing()) {
+        error!("{}", problem);
+        fatal += 1;
+    }
+    if fatal != 0 {
+        return Err(format!("level {:?} has {} problem(s) that prevent editing", path, fatal));
+    }
+    Level::load(path).map_err(|err| format!("could not load level {:?}: {}", path, err))
+}
+
+/// Checks the settings that depend on the schema.
+fn check_settings(settings: &Settings, schema: &Schema) -> Result<(), String> {
+    if let Some((a, b)) = settings.color_swap {
+        let count = schema.colors.len() as u32;
+        if a >= count || b >= count {
+            return Err(format!("cannot swap colors {} and {}; the schema has {} colors",
+                               a,
+                               b,
+                               count));
+        }
+    }
+    match font_path(schema, settings) {
+        Some(ref path) if !path.is_file() => Err(format!("font {:?} not found", path)),
+        Some(_) => Ok(()),
+        None => Err(no_font()),
+    }
+}
+
+/// Returns whether the file at `path` was modified after the one at `other`.
+fn is_newer(path: &Path, other: &Path) -> bool {
+    let modified = |p: &Path| fs::metadata(p).and_then(|meta| meta.modified());
+    match (modified(path), modified(other)) {
+        (Ok(a), Ok(b)) => a > b,
+        (Ok(_), Err(_)) => true,
+        _ => false,
+    }
+}
 
-    start_editor(schema, level, Some("level-output.json"));
+/// Asks whether to restore the level from an autosave that is newer than the level.
+fn recover_autosave(load: Option<&PathBuf>,
+                    save: &Path,
+                    schema: &Schema)
+                    -> Result<Option<Level>, String> {
+    let autosave = autosave_path(save);
+    if !autosave.exists() || !is_newer(&autosave, load.map_or(save, |p| p.as_path())) {
+        return Ok(None);
+    }
+    print!("Found unsaved changes in {:?}, newer than the level. Restore them? [y/N] ",
+           autosave);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|err| format!("could not read answer: {}", err))?;
+    if answer.trim().to_lowercase().starts_with('y') {
+        info!("Restoring {:?}", autosave);
+        load_level(&autosave, schema).map(Some)
+    } else {
+        info!("Keeping {:?} until the next save", autosave);
+        Ok(None)
+    }
+}
+
+fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Edit { schema, load, save, settings } => {
+            let schema = load_schema(&schema)?;
+            check_settings(&settings, &schema)?;
+            let mut level = match load {
+                Some(ref path) => load_level(path, &schema)?,
+                None => Level::new("untitled", &schema.name[..]),
+            };
+            let mut recovered = false;
+            if let Some(ref save) = save {
+                if let Some(autosaved) = recover_autosave(load.as_ref(), save, &schema)? {
+                    level = autosaved;
+                    recovered = true;
+                }
+            }
+            start_editor(schema, level, save, settings, recovered)?;
+        }
+        Command::New { schema, level, name, size, settings } => {
+            if level.exists() {
+                return Err(format!("{:?} already exists", level));
+            }
+            let schema = load_schema(&schema)?;
+            check_settings(&settings, &schema)?;
+            let mut new_level = Level::new(name, &schema.name[..]);
+            new_level.size = size;
+            new_level.save(&level)
+                .map_err(|err| format!("could not create level {:?}: {}", level, err))?;
+            start_editor(schema, new_level, Some(level), settings, false)?;
+        }
+        Command::Resize { schema, level: path, size, anchor } => {
+            let schema = load_schema(&schema)?;
+            let mut level = load_level(&path, &schema)?;
+            let removed = level.resize(size, anchor);
+            level.save(&path).map_err(|err| format!("could not save level {:?}: {}", path, err))?;
+            info!("Resized {:?} to {}x{}, removing {} tile(s)", path, size.0, size.1, removed);
+        }
+        Command::Shift { schema, level: path, by, layer } => {
+            let schema = load_schema(&schema)?;
+            if let Some(ref layer) = layer {
+                if !schema.layers.contains(layer) {
+                    return Err(format!("layer {:?} not known to schema", layer));
+                }
+            }
+            let mut level = load_level(&path, &schema)?;
+            let layer = layer.as_ref().map(|layer| &layer[..]);
+            let by = by.unwrap_or_else(|| level.normalizing_shift(layer));
+            level.shift(by, layer);
+            level.save(&path).map_err(|err| format!("could not save level {:?}: {}", path, err))?;
+            info!("Shifted {:?} by ({}, {})", path, by.0, by.1);
+        }
+        Command::Validate { schema, level } => {
+            let schema = load_schema(&schema)?;
+            let problems = validate::validate_file(&level, &schema)
+                .map_err(|err| format!("could not load level {:?}: {}", level, err))?;
+            for problem in &problems {
+                println!("{}: {}", level.display(), problem);
+            }
+            if !problems.is_empty() {
+                return Err(format!("{} problem(s) found in {:?}", problems.len(), level));
+            }
+            info!("{:?} is valid", level);
+        }
+        Command::CheckSchema { schema: path } => {
+            let schema = load_schema(&path)?;
+            let _image_context = sdl2_image::init(sdl2_image::INIT_PNG | sdl2_image::INIT_JPG)
+                .map_err(|err| format!("could not initialize SDL2_image: {}", err))?;
+            let problems = validate::validate_schema(&schema);
+            for problem in &problems {
+                println!("{}: {}", path.display(), problem);
+            }
+            if !problems.is_empty() {
+                return Err(format!("{} problem(s) found in {:?}", problems.len(), path));
+            }
+            info!("{:?} is valid", path);
+        }
+        Command::ExportPng { schema, level, output, cell_size, area } => {
+            let schema = load_schema(&schema)?;
+            let level = load_level(&level, &schema)?;
+            let _image_context = sdl2_image::init(sdl2_image::INIT_PNG | sdl2_image::INIT_JPG)
+                .map_err(|err| format!("could not initialize SDL2_image: {}", err))?;
+            export::export_png(&level, &schema, &output, cell_size, area)
+                .map_err(|err| format!("could not export {:?}: {}", output, err))?;
+            info!("Exported {:?}", output);
+        }
+        Command::ExportTiled { schema, level, output, cell_size } => {
+            let schema = load_schema(&schema)?;
+            let level = load_level(&level, &schema)?;
+            tiled::save_map(&level, &schema, &output, cell_size)
+                .map_err(|err| format!("could not export {:?}: {}", output, err))?;
+            info!("Exported {:?}", output);
+        }
+        Command::ImportTiled { schema, map, level, name } => {
+            if level.exists() {
+                return Err(format!("{:?} already exists", level));
+            }
+            let schema = load_schema(&schema)?;
+            let mut imported = tiled::load_map(&map, &schema)
+                .map_err(|err| format!("could not import {:?}: {}", map, err))?;
+            if let Some(name) = name {
+                imported.name = name;
+            }
+            imported.save(&level)
+                .map_err(|err| format!("could not save level {:?}: {}", level, err))?;
+            info!("Imported {:?} as {:?}", map, level);
+        }
+    }
+    Ok(())
 }
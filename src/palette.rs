@@ -0,0 +1,149 @@
+use std::cmp;
+
+use sdl2::rect::Rect;
+
+/// The size of the view the panel is docked in.
+const VIEW_SIZE: (u32, u32) = (800, 600);
+/// The width of the panel in view pixels.
+pub const PANEL_WIDTH: u32 = 120;
+const THUMBNAIL_SIZE: u32 = 48;
+/// The space between thumbnails, and between the thumbnails and the edge of the panel.
+const GAP: u32 = 8;
+const COLUMNS: usize = 2;
+const SCROLLBAR_WIDTH: u32 = 4;
+
+/// The side of the view that the palette is attached to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dock {
+    Left,
+    Right,
+}
+
+/// A panel showing every tile as a thumbnail, scrolled by whole rows.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pub dock: Dock,
+    pub visible: bool,
+    /// The number of tiles.
+    count: usize,
+    /// The index of the topmost row of thumbnails shown.
+    first_row: usize,
+}
+
+impl Palette {
+    pub fn new(count: usize) -> Palette {
+        Palette {
+            dock: Dock::Left,
+            visible: true,
+            count: count,
+            first_row: 0,
+        }
+    }
+
+    /// Returns the area of the panel in view coordinates.
+    pub fn rect(&self) -> Rect {
+        let x = match self.dock {
+            Dock::Left => 0,
+            Dock::Right => (VIEW_SIZE.0 - PANEL_WIDTH) as i32,
+        };
+        Rect::new(x, 0, PANEL_WIDTH, VIEW_SIZE.1)
+    }
+
+    /// Returns whether the panel is shown and covers the given view position.
+    #[inline]
+    pub fn contains(&self, point: (i32, i32)) -> bool {
+        self.visible && self.rect().contains(point)
+    }
+
+    #[inline]
+    fn rows(&self) -> usize {
+        (self.count + COLUMNS - 1) / COLUMNS
+    }
+
+    /// Returns how many rows of thumbnails fit in the panel.
+    #[inline]
+    fn visible_rows(&self) -> usize {
+        ((VIEW_SIZE.1 - GAP) / (THUMBNAIL_SIZE + GAP)) as usize
+    }
+
+    #[inline]
+    fn max_first_row(&self) -> usize {
+        self.rows().saturating_sub(self.visible_rows())
+    }
+
+    /// Scrolls up by the given number of rows, or down if negative.
+    pub fn scroll(&mut self, rows: i32) {
+        let first_row = self.first_row as i32 - rows;
+        self.first_row = cmp::max(0, cmp::min(first_row, self.max_first_row() as i32)) as usize;
+    }
+
+    /// Scrolls just enough for the thumbnail of the given tile to be shown.
+    pub fn reveal(&mut self, index: usize) {
+        let row = index / COLUMNS;
+        if row < self.first_row {
+            self.first_row = row;
+        } else if row >= self.first_row + self.visible_rows() {
+            self.first_row = row + 1 - self.visible_rows();
+        }
+    }
+
+    /// Returns the index and view area of every thumbnail currently shown.
+    pub fn thumbnails(&self) -> Vec<(usize, Rect)> {
+        let panel = self.rect();
+        let step = (THUMBNAIL_SIZE + GAP) as i32;
+        let first = self.first_row * COLUMNS;
+        let last = cmp::min(self.count, first + self.visible_rows() * COLUMNS);
+        (first..last)
+            .map(|index| {
+                let row = (index / COLUMNS - self.first_row) as i32;
+                let column = (index % COLUMNS) as i32;
+                let x = panel.x() + GAP as i32 + column * step;
+                let y = panel.y() + GAP as i32 + row * step;
+                (index, Rect::new(x, y, THUMBNAIL_SIZE, THUMBNAIL_SIZE))
+            })
+            .collect()
+    }
+
+    /// Returns the tile whose thumbnail is at the given view position.
+    pub fn tile_at(&self, point: (i32, i32)) -> Option<usize> {
+        if !self.contains(point) {
+            return None;
+        }
+        self.thumbnails()
+            .into_iter()
+            .find(|&(_, rect)| rect.contains(point))
+            .map(|(index, _)| index)
+    }
+
+    /// Returns the area of the scrollbar, if not every row fits in the panel.
+    pub fn scrollbar(&self) -> Option<Rect> {
+        let rows = self.rows();
+        let visible = self.visible_rows();
+        if rows <= visible {
+            return None;
+        }
+        let panel = self.rect();
+        let height = cmp::max(GAP, VIEW_SIZE.1 * visible as u32 / rows as u32);
+        let y = ((VIEW_SIZE.1 - height) as usize * self.first_row / self.max_first_row()) as i32;
+        let x = match self.dock {
+            Dock::Left => panel.x() + (PANEL_WIDTH - SCROLLBAR_WIDTH) as i32,
+            Dock::Right => panel.x(),
+        };
+        Some(Rect::new(x, y, SCROLLBAR_WIDTH, height))
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        info!("Tile palette shown: {}", self.visible);
+    }
+
+    /// Moves the panel to the other side of the view.
+    pub fn swap_dock(&mut self) {
+        self.dock = match self.dock {
+            Dock::Left => Dock::Right,
+            Dock::Right => Dock::Left,
+        };
+        self.visible = true;
+        info!("Tile palette docked: {:?}", self.dock);
+    }
+}
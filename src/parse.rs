@@ -1,90 +1,660 @@
-// Disabled for now.
-
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::path::PathBuf;
 
 use argonaut::{ArgDef, Parse};
-use toml;
 
-const USAGE: &'static str = "Usage: cargo run \
-    [--help | OPTIONS ] schema";
+use common::Settings;
+use level::{self, Anchor};
+use toolbox;
+
+pub const USAGE: &'static str = "\
+Usage: karelizisto [--help | --version]
+       karelizisto edit LEVEL --schema PATH [--save PATH | --no-save] [--font PATH]
+       karelizisto new LEVEL --schema PATH --name NAME [--size W,H]
+       karelizisto resize LEVEL --schema PATH --size W,H [--anchor ANCHOR]
+       karelizisto shift LEVEL --schema PATH (--by DX,DY | --normalize) [--layer NAME]
+       karelizisto validate LEVEL --schema PATH
+       karelizisto check-schema SCHEMA
+       karelizisto export-png LEVEL OUTPUT --schema PATH [--cell-size N] [--area X,Y,X',Y']
+       karelizisto export-tiled LEVEL OUTPUT --schema PATH [--cell-size N]
+       karelizisto import-tiled MAP LEVEL --schema PATH [--name NAME]
+       karelizisto [--load PATH] [--save PATH] [--edit PATH] SCHEMA";
 
 const HELP: &'static str = "\
-Required arguments:
-    schema                  A TOML file describing tiles and layers.
+Commands:
+    edit LEVEL              Edit a level, saving it back to the same file.
+    new LEVEL               Create a new level and edit it.
+    resize LEVEL            Change the size of a level, moving its tiles to keep them
+                            at the anchor and removing those that end up outside.
+    shift LEVEL             Move every tile of a level, or of one layer, by an offset.
+    validate LEVEL          Check a level against its schema without opening a window.
+    check-schema SCHEMA     Check that the textures and tiles of a schema are usable.
+    export-png LEVEL OUTPUT Render a level to a PNG image without opening a window.
+    export-tiled LEVEL OUTPUT
+                            Convert a level to a Tiled map (TMX if OUTPUT ends in .tmx,
+                            JSON otherwise).
+    import-tiled MAP LEVEL  Convert a Tiled map made by export-tiled, or with the same
+                            tile properties, to a new level.
+
+Command arguments:
+    --schema PATH           A TOML file describing tiles and layers.
+    --name NAME             The name of a new level (for import-tiled: default taken
+                            from the map).
+    --save PATH             Save the level to this path instead.
+    --no-save               Never save the level.
+    --cell-size N           The width and height of a cell in pixels (default: 32).
+    --area X,Y,X',Y'        The corners of the cells to render (default: every tile).
+    --size W,H              The width and height of the level, starting at (0, 0)
+                            (for new: default unbounded).
+    --anchor ANCHOR         Where the old tiles stay when resizing: top-left, top,
+                            top-right, left, center, right, bottom-left, bottom or
+                            bottom-right (default: bottom-left).
+    --by DX,DY              How far to move the tiles.
+    --normalize             Move the tiles so that the occupied area starts at (0, 0).
+    --layer NAME            Only move the tiles of this layer (default: every layer).
+
+Without a command:
+    SCHEMA                  A TOML file describing tiles and layers.
+    --load PATH             Load this JSON file as a level.
+    --save PATH             Save the level to this path.
+    --edit PATH             The same as --load PATH --save PATH.
+
+Levels are stored as JSON, or as text grids if the file name ends in .txt.
 
 Optional arguments:
-    --help | -h         Show this message.
-    --version           Show the version of this library.
-    --load PATH         Load this TOML file as a level.
-    --save PATH         Save the level to this path.
-    --edit PATH         The same as --load PATH --save PATH.
+    --help | -h             Show this message.
+    --version               Show the version of this program.
+    --history N             How many edits can be undone (default: 100).
+    --backups N             How many previous versions to keep when saving (default: 3).
+    --swap-colors A,B       The colors exchanged by transforms with color swapping on
+                            (default: 0,1).
+    --font PATH             A TrueType font for text in the editor (default: the font
+                            of the schema). The editor does not start without a font.
 ";
 
-pub fn parse<F>(continuation: F)
-    where F: FnOnce(&str, Option<&str>, Option<&str>)
-{
+/// What the program was asked to do.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Open the editor on an existing level, or an empty one if `load` is missing.
+    Edit {
+        schema: PathBuf,
+        load: Option<PathBuf>,
+        save: Option<PathBuf>,
+        settings: Settings,
+    },
+    /// Create a new level and open the editor on it.
+    New {
+        schema: PathBuf,
+        level: PathBuf,
+        name: String,
+        size: Option<(u32, u32)>,
+        settings: Settings,
+    },
+    /// Change the size of a level.
+    Resize {
+        schema: PathBuf,
+        level: PathBuf,
+        size: (u32, u32),
+        anchor: Anchor,
+    },
+    /// Move the tiles of a level, or of one layer.
+    Shift {
+        schema: PathBuf,
+        level: PathBuf,
+        /// The offset, or `None` to move the occupied area to the origin.
+        by: Option<(i32, i32)>,
+        layer: Option<String>,
+    },
+    /// Check a level against its schema and report every problem.
+    Validate { schema: PathBuf, level: PathBuf },
+    /// Check the textures and tiles of a schema.
+    CheckSchema { schema: PathBuf },
+    /// Render a level to a PNG image.
+    ExportPng {
+        schema: PathBuf,
+        level: PathBuf,
+        output: PathBuf,
+        cell_size: u32,
+        area: Option<(i32, i32, i32, i32)>,
+    },
+    /// Convert a level to a Tiled map.
+    ExportTiled {
+        schema: PathBuf,
+        level: PathBuf,
+        output: PathBuf,
+        cell_size: u32,
+    },
+    /// Convert a Tiled map to a new level.
+    ImportTiled {
+        schema: PathBuf,
+        map: PathBuf,
+        level: PathBuf,
+        name: Option<String>,
+    },
+}
+
+const DEFAULT_CELL_SIZE: u32 = 32;
+
+/// Parses the command line.
+///
+/// Returns `Ok(None)` if the program should exit without doing anything, e.g. after
+/// showing the help.
+pub fn parse() -> Result<Option<Command>, String> {
+    let args: Vec<_> = env::args().skip(1).collect();
+    match args.first().map(|s| &s[..]) {
+        Some("edit") => parse_edit(&args[1..]),
+        Some("new") => parse_new(&args[1..]),
+        Some("resize") => parse_resize(&args[1..]),
+        Some("shift") => parse_shift(&args[1..]),
+        Some("validate") => parse_validate(&args[1..]),
+        Some("check-schema") => parse_check_schema(&args[1..]),
+        Some("export-png") => parse_export_png(&args[1..]),
+        Some("export-tiled") => parse_export_tiled(&args[1..]),
+        Some("import-tiled") => parse_import_tiled(&args[1..]),
+        _ => parse_legacy(&args),
+    }
+}
+
+fn parse_edit(args: &[String]) -> Result<Option<Command>, String> {
+    use argonaut::Arg::*;
+
+    let a_level = ArgDef::positional("level");
+    let a_help = ArgDef::named_and_short("help", 'h').switch();
+    let a_schema = ArgDef::named("schema").option();
+    let a_save = ArgDef::named("save").option();
+    let a_no_save = ArgDef::named("no-save").switch();
+    let a_history = ArgDef::named("history").option();
+    let a_backups = ArgDef::named("backups").option();
+    let a_font = ArgDef::named("font").option();
+    let a_swap_colors = ArgDef::named("swap-colors").option();
+    let expected = &[a_level, a_help, a_schema, a_save, a_no_save, a_history, a_backups, a_font,
+                     a_swap_colors];
+
+    let mut level = None;
+    let mut schema = None;
+    let mut save = None;
+    let mut no_save = false;
+    let mut settings = Settings::default();
+
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
+        match item {
+            Err(err) => return Err(format!("invalid arguments for 'edit': {:?}", err)),
+            Ok(Positional("level", value)) => level = Some(PathBuf::from(value)),
+            Ok(Option("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Option("save", value)) => save = Some(PathBuf::from(value)),
+            Ok(Switch("no-save")) => no_save = true,
+            Ok(Option("history", value)) => settings.history_depth = parse_count(value)?,
+            Ok(Option("backups", value)) => settings.backups = parse_count(value)?,
+            Ok(Option("font", value)) => settings.font = Some(PathBuf::from(value)),
+            Ok(Option("swap-colors", value)) => {
+                settings.color_swap = Some(parse_color_pair(value)?)
+            }
+            Ok(Switch("help")) => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    let level = level.ok_or("'edit' needs the path of a level")?;
+    let schema = schema.ok_or("'edit' needs a schema (--schema PATH)")?;
+    if no_save && save.is_some() {
+        return Err("--no-save and --save cannot be used together".to_owned());
+    }
+    let save = if no_save {
+        None
+    } else {
+        Some(save.unwrap_or_else(|| level.clone()))
+    };
+    Ok(Some(Command::Edit {
+        schema: schema,
+        load: Some(level),
+        save: save,
+        settings: settings,
+    }))
+}
+
+fn parse_new(args: &[String]) -> Result<Option<Command>, String> {
+    use argonaut::Arg::*;
+
+    let a_level = ArgDef::positional("level");
+    let a_help = ArgDef::named_and_short("help", 'h').switch();
+    let a_schema = ArgDef::named("schema").option();
+    let a_name = ArgDef::named("name").option();
+    let a_size = ArgDef::named("size").option();
+    let a_history = ArgDef::named("history").option();
+    let a_backups = ArgDef::named("backups").option();
+    let a_font = ArgDef::named("font").option();
+    let a_swap_colors = ArgDef::named("swap-colors").option();
+    let expected = &[a_level, a_help, a_schema, a_name, a_size, a_history, a_backups, a_font,
+                     a_swap_colors];
+
+    let mut level = None;
+    let mut schema = None;
+    let mut name = None;
+    let mut size = None;
+    let mut settings = Settings::default();
+
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
+        match item {
+            Err(err) => return Err(format!("invalid arguments for 'new': {:?}", err)),
+            Ok(Positional("level", value)) => level = Some(PathBuf::from(value)),
+            Ok(Option("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Option("name", value)) => name = Some(value.to_owned()),
+            Ok(Option("size", value)) => size = Some(parse_size(value)?),
+            Ok(Option("history", value)) => settings.history_depth = parse_count(value)?,
+            Ok(Option("backups", value)) => settings.backups = parse_count(value)?,
+            Ok(Option("font", value)) => settings.font = Some(PathBuf::from(value)),
+            Ok(Option("swap-colors", value)) => {
+                settings.color_swap = Some(parse_color_pair(value)?)
+            }
+            Ok(Switch("help")) => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(Some(Command::New {
+        level: level.ok_or("'new' needs the path of the level to create")?,
+        schema: schema.ok_or("'new' needs a schema (--schema PATH)")?,
+        name: name.ok_or("'new' needs a level name (--name NAME)")?,
+        size: size,
+        settings: settings,
+    }))
+}
+
+fn parse_resize(args: &[String]) -> Result<Option<Command>, String> {
+    use argonaut::Arg::*;
+
+    let a_level = ArgDef::positional("level");
+    let a_help = ArgDef::named_and_short("help", 'h').switch();
+    let a_schema = ArgDef::named("schema").option();
+    let a_size = ArgDef::named("size").option();
+    let a_anchor = ArgDef::named("anchor").option();
+    let expected = &[a_level, a_help, a_schema, a_size, a_anchor];
+
+    let mut level = None;
+    let mut schema = None;
+    let mut size = None;
+    let mut anchor = Anchor::BottomLeft;
+
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
+        match item {
+            Err(err) => return Err(format!("invalid arguments for 'resize': {:?}", err)),
+            Ok(Positional("level", value)) => level = Some(PathBuf::from(value)),
+            Ok(Option("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Option("size", value)) => size = Some(parse_size(value)?),
+            Ok(Option("anchor", value)) => anchor = value.parse::<Anchor>()?,
+            Ok(Switch("help")) => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(Some(Command::Resize {
+        level: level.ok_or("'resize' needs the path of a level")?,
+        schema: schema.ok_or("'resize' needs a schema (--schema PATH)")?,
+        size: size.ok_or("'resize' needs the new size (--size W,H)")?,
+        anchor: anchor,
+    }))
+}
+
+fn parse_shift(args: &[String]) -> Result<Option<Command>, String> {
+    use argonaut::Arg::*;
+
+    let a_level = ArgDef::positional("level");
+    let a_help = ArgDef::named_and_short("help", 'h').switch();
+    let a_schema = ArgDef::named("schema").option();
+    let a_by = ArgDef::named("by").option();
+    let a_normalize = ArgDef::named("normalize").switch();
+    let a_layer = ArgDef::named("layer").option();
+    let expected = &[a_level, a_help, a_schema, a_by, a_normalize, a_layer];
+
+    let mut level = None;
+    let mut schema = None;
+    let mut by = None;
+    let mut normalize = false;
+    let mut layer = None;
+
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
+        match item {
+            Err(err) => return Err(format!("invalid arguments for 'shift': {:?}", err)),
+            Ok(Positional("level", value)) => level = Some(PathBuf::from(value)),
+            Ok(Option("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Option("by", value)) => by = Some(parse_offset(value)?),
+            Ok(Switch("normalize")) => normalize = true,
+            Ok(Option("layer", value)) => layer = Some(value.to_owned()),
+            Ok(Switch("help")) => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if normalize == by.is_some() {
+        return Err("'shift' needs exactly one of --by DX,DY and --normalize".to_owned());
+    }
+    Ok(Some(Command::Shift {
+        level: level.ok_or("'shift' needs the path of a level")?,
+        schema: schema.ok_or("'shift' needs a schema (--schema PATH)")?,
+        by: by,
+        layer: layer,
+    }))
+}
+
+fn parse_validate(args: &[String]) -> Result<Option<Command>, String> {
+    use argonaut::Arg::*;
+
+    let a_level = ArgDef::positional("level");
+    let a_help = ArgDef::named_and_short("help", 'h').switch();
+    let a_schema = ArgDef::named("schema").option();
+    let expected = &[a_level, a_help, a_schema];
+
+    let mut level = None;
+    let mut schema = None;
+
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
+        match item {
+            Err(err) => return Err(format!("invalid arguments for 'validate': {:?}", err)),
+            Ok(Positional("level", value)) => level = Some(PathBuf::from(value)),
+            Ok(Option("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Switch("help")) => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(Some(Command::Validate {
+        level: level.ok_or("'validate' needs the path of a level")?,
+        schema: schema.ok_or("'validate' needs a schema (--schema PATH)")?,
+    }))
+}
+
+fn parse_check_schema(args: &[String]) -> Result<Option<Command>, String> {
+    use argonaut::Arg::*;
+
+    let a_schema = ArgDef::positional("schema");
+    let a_help = ArgDef::named_and_short("help", 'h').switch();
+    let expected = &[a_schema, a_help];
+
+    let mut schema = None;
+
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
+        match item {
+            Err(err) => return Err(format!("invalid arguments for 'check-schema': {:?}", err)),
+            Ok(Positional("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Switch("help")) => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(Some(Command::CheckSchema {
+        schema: schema.ok_or("'check-schema' needs the path of a schema")?,
+    }))
+}
+
+/// Parses a non-negative number.
+fn parse_count(text: &str) -> Result<usize, String> {
+    text.parse::<usize>().map_err(|err| format!("invalid number {:?}: {}", text, err))
+}
+
+/// Parses an area given as `X,Y,X',Y'`, with the corners in any order.
+fn parse_area(text: &str) -> Result<(i32, i32, i32, i32), String> {
+    let parts = text.split(',')
+        .map(|part| part.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("invalid area {:?}: {}", text, err))?;
+    if parts.len() != 4 {
+        return Err(format!("invalid area {:?}: expected four numbers", text));
+    }
+    Ok(toolbox::span((parts[0], parts[1]), (parts[2], parts[3])))
+}
+
+/// Parses a size given as `W,H`.
+fn parse_size(text: &str) -> Result<(u32, u32), String> {
+    let parts = text.split(',')
+        .map(|part| part.trim().parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("invalid size {:?}: {}", text, err))?;
+    if parts.len() != 2 {
+        return Err(format!("invalid size {:?}: expected two numbers", text));
+    }
+    let size = (parts[0], parts[1]);
+    level::check_size(size)?;
+    Ok(size)
+}
+
+/// Parses two color indices given as `A,B`.
+fn parse_color_pair(text: &str) -> Result<(u32, u32), String> {
+    let parts = text.split(',')
+        .map(|part| part.trim().parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("invalid colors {:?}: {}", text, err))?;
+    if parts.len() != 2 {
+        return Err(format!("invalid colors {:?}: expected two numbers", text));
+    }
+    Ok((parts[0], parts[1]))
+}
+
+/// Parses an offset given as `DX,DY`.
+fn parse_offset(text: &str) -> Result<(i32, i32), String> {
+    let parts = text.split(',')
+        .map(|part| part.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("invalid offset {:?}: {}", text, err))?;
+    if parts.len() != 2 {
+        return Err(format!("invalid offset {:?}: expected two numbers", text));
+    }
+    Ok((parts[0], parts[1]))
+}
+
+fn parse_export_png(args: &[String]) -> Result<Option<Command>, String> {
+    use argonaut::Arg::*;
+
+    let a_level = ArgDef::positional("level");
+    let a_output = ArgDef::positional("output");
+    let a_help = ArgDef::named_and_short("help", 'h').switch();
+    let a_schema = ArgDef::named("schema").option();
+    let a_cell_size = ArgDef::named("cell-size").option();
+    let a_area = ArgDef::named("area").option();
+    let expected = &[a_level, a_output, a_help, a_schema, a_cell_size, a_area];
+
+    let mut level = None;
+    let mut output = None;
+    let mut schema = None;
+    let mut cell_size = DEFAULT_CELL_SIZE;
+    let mut area = None;
+
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
+        match item {
+            Err(err) => return Err(format!("invalid arguments for 'export-png': {:?}", err)),
+            Ok(Positional("level", value)) => level = Some(PathBuf::from(value)),
+            Ok(Positional("output", value)) => output = Some(PathBuf::from(value)),
+            Ok(Option("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Option("cell-size", value)) => {
+                cell_size = value.parse::<u32>()
+                    .map_err(|err| format!("invalid cell size {:?}: {}", value, err))?;
+            }
+            Ok(Option("area", value)) => area = Some(parse_area(value)?),
+            Ok(Switch("help")) => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(Some(Command::ExportPng {
+        level: level.ok_or("'export-png' needs the path of a level")?,
+        output: output.ok_or("'export-png' needs the path of the image to write")?,
+        schema: schema.ok_or("'export-png' needs a schema (--schema PATH)")?,
+        cell_size: cell_size,
+        area: area,
+    }))
+}
+
+fn parse_export_tiled(args: &[String]) -> Result<Option<Command>, String> {
+    use argonaut::Arg::*;
+
+    let a_level = ArgDef::positional("level");
+    let a_output = ArgDef::positional("output");
+    let a_help = ArgDef::named_and_short("help", 'h').switch();
+    let a_schema = ArgDef::named("schema").option();
+    let a_cell_size = ArgDef::named("cell-size").option();
+    let expected = &[a_level, a_output, a_help, a_schema, a_cell_size];
+
+    let mut level = None;
+    let mut output = None;
+    let mut schema = None;
+    let mut cell_size = DEFAULT_CELL_SIZE;
+
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
+        match item {
+            Err(err) => return Err(format!("invalid arguments for 'export-tiled': {:?}", err)),
+            Ok(Positional("level", value)) => level = Some(PathBuf::from(value)),
+            Ok(Positional("output", value)) => output = Some(PathBuf::from(value)),
+            Ok(Option("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Option("cell-size", value)) => {
+                cell_size = value.parse::<u32>()
+                    .map_err(|err| format!("invalid cell size {:?}: {}", value, err))?;
+            }
+            Ok(Switch("help")) => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(Some(Command::ExportTiled {
+        level: level.ok_or("'export-tiled' needs the path of a level")?,
+        output: output.ok_or("'export-tiled' needs the path of the map to write")?,
+        schema: schema.ok_or("'export-tiled' needs a schema (--schema PATH)")?,
+        cell_size: cell_size,
+    }))
+}
+
+fn parse_import_tiled(args: &[String]) -> Result<Option<Command>, String> {
+    use argonaut::Arg::*;
+
+    let a_map = ArgDef::positional("map");
+    let a_level = ArgDef::positional("level");
+    let a_help = ArgDef::named_and_short("help", 'h').switch();
+    let a_schema = ArgDef::named("schema").option();
+    let a_name = ArgDef::named("name").option();
+    let expected = &[a_map, a_level, a_help, a_schema, a_name];
+
+    let mut map = None;
+    let mut level = None;
+    let mut schema = None;
+    let mut name = None;
+
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
+        match item {
+            Err(err) => return Err(format!("invalid arguments for 'import-tiled': {:?}", err)),
+            Ok(Positional("map", value)) => map = Some(PathBuf::from(value)),
+            Ok(Positional("level", value)) => level = Some(PathBuf::from(value)),
+            Ok(Option("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Option("name", value)) => name = Some(value.to_owned()),
+            Ok(Switch("help")) => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(Some(Command::ImportTiled {
+        map: map.ok_or("'import-tiled' needs the path of a map")?,
+        level: level.ok_or("'import-tiled' needs the path of the level to create")?,
+        schema: schema.ok_or("'import-tiled' needs a schema (--schema PATH)")?,
+        name: name,
+    }))
+}
+
+fn parse_legacy(args: &[String]) -> Result<Option<Command>, String> {
     use argonaut::Arg::*;
 
-    // Create the arguments
     let a_schema = ArgDef::positional("schema");
     let a_version = ArgDef::named("version").switch();
     let a_help = ArgDef::named_and_short("help", 'h').switch();
     let a_load = ArgDef::named("load").option();
     let a_save = ArgDef::named("save").option();
     let a_edit = ArgDef::named("edit").option();
+    let a_history = ArgDef::named("history").option();
+    let a_backups = ArgDef::named("backups").option();
+    let a_font = ArgDef::named("font").option();
+    let a_swap_colors = ArgDef::named("swap-colors").option();
+    let expected = &[a_schema, a_version, a_help, a_load, a_save, a_edit, a_history, a_backups,
+                     a_font, a_swap_colors];
 
-    let args: Vec<_> = env::args().skip(1).collect();
-
-    // Prepare the options
-    let mut schema = "";
+    let mut schema = None;
     let mut load = None;
     let mut save = None;
     let mut edit = None;
+    let mut settings = Settings::default();
 
-    let expected = &[a_schema, a_version, a_help, a_load, a_save, a_edit];
-
-    let mut parse = Parse::new(expected, &args).expect("Invalid definitions");
-    while let Some(item) = parse.next() {
+    let parse = Parse::new(expected, args).expect("Invalid definitions");
+    for item in parse {
         match item {
-            Err(_) => {
-                // info!("Parse error: {:?}", err);
-                info!("{}", USAGE);
-                return;
-            }
-            Ok(Positional("schema", value)) => schema = value,
-            Ok(Option("load", value)) => load = Some(value),
-            Ok(Option("save", value)) => save = Some(value),
-            Ok(Option("edit", value)) => edit = Some(value),
+            Err(err) => return Err(format!("invalid arguments: {:?}", err)),
+            Ok(Positional("schema", value)) => schema = Some(PathBuf::from(value)),
+            Ok(Option("load", value)) => load = Some(PathBuf::from(value)),
+            Ok(Option("save", value)) => save = Some(PathBuf::from(value)),
+            Ok(Option("edit", value)) => edit = Some(PathBuf::from(value)),
+            Ok(Option("history", value)) => settings.history_depth = parse_count(value)?,
+            Ok(Option("backups", value)) => settings.backups = parse_count(value)?,
+            Ok(Option("font", value)) => settings.font = Some(PathBuf::from(value)),
+            Ok(Option("swap-colors", value)) => {
+                settings.color_swap = Some(parse_color_pair(value)?)
+            }
             Ok(Switch("help")) => {
-                info!("{}\n\n{}", USAGE, HELP);
-                return None;
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(None);
             }
             Ok(Switch("version")) => {
-                return info!("{}", env!("CARGO_PKG_VERSION"));
+                println!("{}", env!("CARGO_PKG_VERSION"));
+                return Ok(None);
             }
             _ => unreachable!(),
         }
     }
 
-    // Use the variables holding the parsed values for something
-    info!("Parsed succesfully!");
-    info!("Schema: {}", schema);
-    info!("Load: {:?}", load);
-    info!("Save: {:?}", save);
-    info!("Edit: {:?}", edit);
-
     if let Some(path) = edit {
         if load.is_some() {
-            return info!("Error: --edit overwrites --load");
+            return Err("--edit cannot be combined with --load".to_owned());
         }
         if save.is_some() {
-            return info!("Error: --edit overwrites --save");
+            return Err("--edit cannot be combined with --save".to_owned());
         }
-        load = Some(path);
+        load = Some(path.clone());
         save = Some(path);
     }
 
-    continuation(schema, load, save)
+    Ok(Some(Command::Edit {
+        schema: schema.ok_or("missing the schema argument")?,
+        load: load,
+        save: save,
+        settings: settings,
+    }))
 }
@@ -5,6 +5,7 @@ use std::path::PathBuf;
 pub struct SpriteSpec {
     pub texture: String,
     pub area: Option<(u32, u32, u32, u32)>,
+    pub layers: Option<Vec<String>>,
 }
 
 #[derive(Deserialize)]
@@ -12,6 +13,7 @@ pub struct SchemaSpec {
     pub name: String,
     pub layers: Vec<String>,
     pub prefix: PathBuf,
+    pub font: Option<String>,
     pub colors: Vec<String>,
     pub tiles: HashMap<String, SpriteSpec>,
 }
@@ -0,0 +1,76 @@
+/// How painted cells are repeated around the symmetry center.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symmetry {
+    /// Mirrored left to right, across a vertical axis.
+    Horizontal,
+    /// Mirrored top to bottom, across a horizontal axis.
+    Vertical,
+    /// Mirrored across both axes.
+    Both,
+    /// Rotated half a turn.
+    Rotate2,
+    /// Rotated in quarter turns.
+    Rotate4,
+}
+
+impl Symmetry {
+    /// Returns the mode that follows this one, or `None` to turn symmetry off.
+    pub fn next(mode: Option<Symmetry>) -> Option<Symmetry> {
+        use self::Symmetry::*;
+        match mode {
+            None => Some(Horizontal),
+            Some(Horizontal) => Some(Vertical),
+            Some(Vertical) => Some(Both),
+            Some(Both) => Some(Rotate2),
+            Some(Rotate2) => Some(Rotate4),
+            Some(Rotate4) => None,
+        }
+    }
+
+    #[inline]
+    pub fn mirrors_x(self) -> bool {
+        self == Symmetry::Horizontal || self == Symmetry::Both
+    }
+
+    #[inline]
+    pub fn mirrors_y(self) -> bool {
+        self == Symmetry::Vertical || self == Symmetry::Both
+    }
+}
+
+#[inline]
+fn half_floor(value: i32) -> i32 {
+    if value < 0 { (value - 1) / 2 } else { value / 2 }
+}
+
+/// Returns the cells that correspond to `pos` under the given symmetry.
+///
+/// `center` is given in half cells, so that the axes can lie both on cell edges and
+/// through the middle of cells. The first cell is always `pos` itself, and no cell is
+/// repeated.
+pub fn images(mode: Symmetry, center: (i32, i32), pos: (i32, i32)) -> Vec<(i32, i32)> {
+    let (a, b) = center;
+    let (x, y) = pos;
+    let mx = a - x - 1;
+    let my = b - y - 1;
+    let candidates = match mode {
+        Symmetry::Horizontal => vec![pos, (mx, y)],
+        Symmetry::Vertical => vec![pos, (x, my)],
+        Symmetry::Both => vec![pos, (mx, y), (mx, my), (x, my)],
+        Symmetry::Rotate2 => vec![pos, (mx, my)],
+        Symmetry::Rotate4 => {
+            let sum = half_floor(a + b);
+            let diff = half_floor(b - a);
+            let quarter = (sum - y - 1, diff + x);
+            let three_quarters = (a - quarter.0 - 1, b - quarter.1 - 1);
+            vec![pos, quarter, (mx, my), three_quarters]
+        }
+    };
+    let mut result = Vec::with_capacity(candidates.len());
+    for cell in candidates {
+        if !result.contains(&cell) {
+            result.push(cell);
+        }
+    }
+    result
+}
@@ -0,0 +1,48 @@
+use glorious::{Color, Renderer};
+use sdl2::pixels;
+use sdl2::rect::Rect;
+use sdl2::render::Texture;
+use sdl2_ttf::Font;
+
+/// A line of text, rendered once into a texture in a single color.
+pub struct Text {
+    pub width: u32,
+    pub height: u32,
+    /// The rendered text, or `None` if it is empty, since SDL_ttf cannot render that.
+    texture: Option<Texture>,
+}
+
+impl Text {
+    pub fn render(renderer: &mut Renderer,
+                  font: &Font,
+                  text: &str,
+                  color: Color)
+                  -> Result<Text, String> {
+        if text.is_empty() {
+            return Ok(Text {
+                width: 0,
+                height: 0,
+                texture: None,
+            });
+        }
+        let Color(r, g, b, a) = color;
+        let surface = font.render(text)
+            .blended(pixels::Color::RGBA(r, g, b, a))
+            .map_err(|err| format!("could not render {:?}: {:?}", text, err))?;
+        let texture = renderer.create_texture_from_surface(&surface)
+            .map_err(|err| format!("could not create a texture for {:?}: {:?}", text, err))?;
+        Ok(Text {
+            width: surface.width(),
+            height: surface.height(),
+            texture: Some(texture),
+        })
+    }
+
+    /// Draws the text with its top-left corner at the given view position.
+    pub fn draw(&self, renderer: &mut Renderer, pos: (i32, i32)) {
+        if let Some(ref texture) = self.texture {
+            let rect = Rect::new(pos.0, pos.1, self.width, self.height);
+            renderer.copy(texture, None, Some(rect));
+        }
+    }
+}
@@ -0,0 +1,994 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::i32;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use json::{self, Value};
+use xml::reader::{EventReader, XmlEvent};
+
+use info::Schema;
+use level::{Layer, Level, Tile};
+
+/// The property of a tileset tile naming the schema tile it stands for.
+const TILE_PROPERTY: &'static str = "tile";
+/// The property of a tileset tile giving the color of the tile.
+const COLOR_PROPERTY: &'static str = "color";
+const LEVEL_PROPERTY: &'static str = "level";
+const SCHEMA_PROPERTY: &'static str = "schema";
+/// The model position of the top-left cell of the map.
+const ORIGIN_X_PROPERTY: &'static str = "origin_x";
+const ORIGIN_Y_PROPERTY: &'static str = "origin_y";
+/// The size of a bounded level.
+const LEVEL_WIDTH_PROPERTY: &'static str = "level_width";
+const LEVEL_HEIGHT_PROPERTY: &'static str = "level_height";
+
+/// The global id of the first tile of the generated tileset.
+const FIRST_GID: u32 = 1;
+/// The bits of a global tile id that Tiled uses to flip and rotate tiles.
+const FLIP_FLAGS: u32 = 0xf0000000;
+
+/// One of the map formats of Tiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The XML format, usually with the extension `.tmx`.
+    Tmx,
+    /// The JSON format, usually with the extension `.json` or `.tmj`.
+    Json,
+}
+
+impl Format {
+    /// Returns the format matching the extension of a path, defaulting to JSON.
+    pub fn from_path(path: &Path) -> Format {
+        let extension = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match extension.as_ref().map(|ext| &ext[..]) {
+            Some("tmx") => Format::Tmx,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// The value of a custom property.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Property {
+    Str(String),
+    Int(i64),
+}
+
+pub type Properties = BTreeMap<String, Property>;
+
+/// A tile of a tileset, showing an area of its own image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TilesetTile {
+    pub id: u32,
+    pub image: String,
+    /// The area of the image to show, or `None` for all of it.
+    pub area: Option<(u32, u32, u32, u32)>,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tileset {
+    pub first_gid: u32,
+    pub name: String,
+    pub tiles: Vec<TilesetTile>,
+}
+
+/// A tile layer covering the whole map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileLayer {
+    pub name: String,
+    /// The global tile ids of the cells, row by row from the top, with 0 for no tile.
+    pub data: Vec<u32>,
+}
+
+/// The parts of an orthogonal Tiled map that matter to levels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Map {
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: (u32, u32),
+    pub properties: Properties,
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<TileLayer>,
+}
+
+/// Converts a level to a map with a tileset generated from the schema.
+///
+/// Each schema tile gets a tileset tile for every color, with the tile name and color as
+/// custom properties, so that the level can be converted back without losing anything.
+/// The map covers the occupied part of the level, and its origin is kept as properties.
+/// Image paths are relative to `map_dir`, the directory of the map file, since that is how
+/// Tiled resolves them.
+pub fn to_map(level: &Level,
+              schema: &Schema,
+              map_dir: &Path,
+              cell_size: u32)
+              -> Result<Map, String> {
+    let current_dir = env::current_dir()
+        .map_err(|err| format!("could not find the current directory: {}", err))?;
+    let prefix = relative_path(&current_dir.join(map_dir), &current_dir.join(&schema.prefix));
+    let mut names = schema.tiles.keys().collect::<Vec<_>>();
+    names.sort();
+    let num_colors = schema.colors.len() as u32;
+    let mut tiles = Vec::new();
+    let mut gids = HashMap::new();
+    for (i, name) in names.into_iter().enumerate() {
+        let info = &schema.tiles[name];
+        let image = prefix.join(&info.texture).to_string_lossy().into_owned();
+        for color in 0..num_colors {
+            let id = i as u32 * num_colors + color;
+            let mut properties = Properties::new();
+            properties.insert(TILE_PROPERTY.to_owned(), Property::Str(name.clone()));
+            properties.insert(COLOR_PROPERTY.to_owned(), Property::Int(color as i64));
+            tiles.push(TilesetTile {
+                id: id,
+                image: image.clone(),
+                area: info.area,
+                properties: properties,
+            });
+            gids.insert((&name[..], color), FIRST_GID + id);
+        }
+    }
+
+    for layer_name in level.layers.keys() {
+        if !schema.layers.contains(layer_name) {
+            return Err(format!("layers.{}: layer not known to schema", layer_name));
+        }
+    }
+    let (x1, y1, x2, y2) = level.occupied_bounds().unwrap_or((0, 0, 0, 0));
+    let (width, height) = ((x2 - x1 + 1) as u32, (y2 - y1 + 1) as u32);
+    let mut layers = Vec::new();
+    for layer_name in &schema.layers {
+        let layer = match level.layers.get(layer_name) {
+            Some(layer) => layer,
+            None => continue,
+        };
+        let mut data = vec![0; width as usize * height as usize];
+        for (pos, tile) in layer {
+            let gid = match gids.get(&(&tile.name[..], tile.color)) {
+                Some(&gid) => gid,
+                None => {
+                    return Err(format!("layers.{}: tile {:?} with color {} at ({}, {}) is \
+                                        not in the schema",
+                                       layer_name,
+                                       tile.name,
+                                       tile.color,
+                                       pos.0,
+                                       pos.1))
+                }
+            };
+            // Tiled counts rows from the top, while the model y axis points up.
+            let index = (y2 - pos.1) as usize * width as usize + (pos.0 - x1) as usize;
+            data[index] = gid;
+        }
+        layers.push(TileLayer {
+            name: layer_name.clone(),
+            data: data,
+        });
+    }
+
+    let mut properties = Properties::new();
+    properties.insert(LEVEL_PROPERTY.to_owned(), Property::Str(level.name.clone()));
+    properties.insert(SCHEMA_PROPERTY.to_owned(), Property::Str(level.schema.clone()));
+    properties.insert(ORIGIN_X_PROPERTY.to_owned(), Property::Int(x1 as i64));
+    properties.insert(ORIGIN_Y_PROPERTY.to_owned(), Property::Int(y2 as i64));
+    if let Some((w, h)) = level.size {
+        properties.insert(LEVEL_WIDTH_PROPERTY.to_owned(), Property::Int(w as i64));
+        properties.insert(LEVEL_HEIGHT_PROPERTY.to_owned(), Property::Int(h as i64));
+    }
+    Ok(Map {
+        width: width,
+        height: height,
+        tile_size: (cell_size, cell_size),
+        properties: properties,
+        tilesets: vec![Tileset {
+                           first_gid: FIRST_GID,
+                           name: schema.name.clone(),
+                           tiles: tiles,
+                       }],
+        layers: layers,
+    })
+}
+
+/// Converts a map back to a level.
+///
+/// Every tile used in the map must have a `tile` property naming a schema tile, and may
+/// have a `color` property. Without origin properties, the bottom-left cell of the map
+/// ends up at `(0, 0)`.
+pub fn from_map(map: &Map, schema: &Schema) -> Result<Level, String> {
+    if let Some(&Property::Str(ref name)) = map.properties.get(SCHEMA_PROPERTY) {
+        if *name != schema.name {
+            return Err(format!("the map uses schema {:?}, but the schema is {:?}",
+                               name,
+                               schema.name));
+        }
+    }
+
+    let num_colors = schema.colors.len() as i64;
+    let mut lookup = HashMap::new();
+    for tileset in &map.tilesets {
+        for tile in &tileset.tiles {
+            let context = format!("tile {} of tileset {:?}", tile.id, tileset.name);
+            let name = match tile.properties.get(TILE_PROPERTY) {
+                Some(&Property::Str(ref name)) => name.clone(),
+                _ => return Err(format!("{}: missing {:?} property", context, TILE_PROPERTY)),
+            };
+            if !schema.tiles.contains_key(&name) {
+                return Err(format!("{}: tile {:?} not known to schema", context, name));
+            }
+            let color = match tile.properties.get(COLOR_PROPERTY) {
+                None => 0,
+                Some(&Property::Int(color)) if 0 <= color && color < num_colors => color as u32,
+                Some(other) => return Err(format!("{}: invalid color {:?}", context, other)),
+            };
+            lookup.insert(tileset.first_gid + tile.id, Tile::new(name, color));
+        }
+    }
+
+    let int_property = |name: &str, default: i64| match map.properties.get(name) {
+        Some(&Property::Int(value)) => value,
+        _ => default,
+    };
+    let origin_x = int_property(ORIGIN_X_PROPERTY, 0) as i32;
+    let origin_y = int_property(ORIGIN_Y_PROPERTY, map.height as i64 - 1) as i32;
+    let name = match map.properties.get(LEVEL_PROPERTY) {
+        Some(&Property::Str(ref name)) => &name[..],
+        _ => "untitled",
+    };
+
+    let mut level = Level::new(name, &schema.name[..]);
+    let width = map.properties.get(LEVEL_WIDTH_PROPERTY);
+    let height = map.properties.get(LEVEL_HEIGHT_PROPERTY);
+    // Every cell of the level must have an `i32` position.
+    let in_range = |value: i64| 0 <= value && value <= i32::MAX as i64;
+    match (width, height) {
+        (Some(&Property::Int(w)), Some(&Property::Int(h))) if in_range(w) && in_range(h) => {
+            level.size = Some((w as u32, h as u32));
+        }
+        (None, None) => {}
+        (w, h) => return Err(format!("invalid level size {:?}x{:?}", w, h)),
+    }
+    for map_layer in &map.layers {
+        if !schema.layers.contains(&map_layer.name) {
+            return Err(format!("layer {:?} not known to schema", map_layer.name));
+        }
+        if level.layers.contains_key(&map_layer.name) {
+            return Err(format!("layer {:?} appears more than once", map_layer.name));
+        }
+        if map_layer.data.len() != map.width as usize * map.height as usize {
+            return Err(format!("layer {:?} has {} cells, but the map has {}x{}",
+                               map_layer.name,
+                               map_layer.data.len(),
+                               map.width,
+                               map.height));
+        }
+        let mut layer = Layer::new();
+        for (i, &gid) in map_layer.data.iter().enumerate() {
+            // Flipped tiles are placed unflipped, since levels cannot flip tiles.
+            let gid = gid & !FLIP_FLAGS;
+            if gid == 0 {
+                continue;
+            }
+            let tile = match lookup.get(&gid) {
+                Some(tile) => tile,
+                None => {
+                    return Err(format!("layer {:?}: unknown tile id {}", map_layer.name, gid))
+                }
+            };
+            let pos = (origin_x + (i % map.width as usize) as i32,
+                       origin_y - (i / map.width as usize) as i32);
+            layer.set(pos, tile.clone());
+        }
+        level.layers.insert(map_layer.name.clone(), layer);
+    }
+    Ok(level)
+}
+
+/// Returns the path of `to` relative to the directory `from`.
+///
+/// Both paths must be absolute, or relative to the same directory.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = from.components().filter(|&c| c != Component::CurDir).collect::<Vec<_>>();
+    let to = to.components().filter(|&c| c != Component::CurDir).collect::<Vec<_>>();
+    let common = from.iter().zip(&to).take_while(|&(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component.as_os_str());
+    }
+    path
+}
+
+/// Writes a level to a map file, in the format matching its extension.
+pub fn save_map<P>(level: &Level, schema: &Schema, path: P, cell_size: u32) -> Result<(), String>
+    where P: AsRef<Path>
+{
+    let path = path.as_ref();
+    let map = to_map(level, schema, path.parent().unwrap_or(Path::new("")), cell_size)?;
+    let mut writer = BufWriter::new(File::create(path).map_err(|err| err.to_string())?);
+    match Format::from_path(path) {
+        Format::Tmx => write_tmx(&map, &mut writer).map_err(|err| err.to_string())?,
+        Format::Json => write_json(&map, &mut writer)?,
+    }
+    writer.flush().map_err(|err| err.to_string())
+}
+
+/// Reads a level from a map file, in the format matching its extension.
+pub fn load_map<P>(path: P, schema: &Schema) -> Result<Level, String>
+    where P: AsRef<Path>
+{
+    let path = path.as_ref();
+    let reader = BufReader::new(File::open(path).map_err(|err| err.to_string())?);
+    let map = match Format::from_path(path) {
+        Format::Tmx => read_tmx(reader)?,
+        Format::Json => read_json(reader)?,
+    };
+    from_map(&map, schema)
+}
+
+/// Escapes text for use in XML attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_tmx_properties<W>(writer: &mut W, properties: &Properties, indent: &str) -> io::Result<()>
+    where W: Write
+{
+    if properties.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "{}<properties>", indent)?;
+    for (name, value) in properties {
+        match *value {
+            Property::Str(ref value) => {
+                writeln!(writer,
+                         "{} <property name=\"{}\" value=\"{}\"/>",
+                         indent,
+                         escape(name),
+                         escape(value))?
+            }
+            Property::Int(value) => {
+                writeln!(writer,
+                         "{} <property name=\"{}\" type=\"int\" value=\"{}\"/>",
+                         indent,
+                         escape(name),
+                         value)?
+            }
+        }
+    }
+    writeln!(writer, "{}</properties>", indent)
+}
+
+/// Writes a map as TMX, with tilesets embedded and layers encoded as CSV.
+pub fn write_tmx<W>(map: &Map, writer: &mut W) -> io::Result<()>
+    where W: Write
+{
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(writer,
+             "<map version=\"1.10\" orientation=\"orthogonal\" renderorder=\"right-down\" \
+              width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" infinite=\"0\" \
+              nextlayerid=\"{}\" nextobjectid=\"1\">",
+             map.width,
+             map.height,
+             map.tile_size.0,
+             map.tile_size.1,
+             map.layers.len() + 1)?;
+    write_tmx_properties(writer, &map.properties, " ")?;
+    for tileset in &map.tilesets {
+        writeln!(writer,
+                 " <tileset firstgid=\"{}\" name=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" \
+                  tilecount=\"{}\" columns=\"0\">",
+                 tileset.first_gid,
+                 escape(&tileset.name),
+                 map.tile_size.0,
+                 map.tile_size.1,
+                 tileset.tiles.len())?;
+        writeln!(writer, "  <grid orientation=\"orthogonal\" width=\"1\" height=\"1\"/>")?;
+        for tile in &tileset.tiles {
+            match tile.area {
+                Some((x, y, w, h)) => {
+                    writeln!(writer,
+                             "  <tile id=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\">",
+                             tile.id,
+                             x,
+                             y,
+                             w,
+                             h)?
+                }
+                None => writeln!(writer, "  <tile id=\"{}\">", tile.id)?,
+            }
+            write_tmx_properties(writer, &tile.properties, "   ")?;
+            writeln!(writer, "   <image source=\"{}\"/>", escape(&tile.image))?;
+            writeln!(writer, "  </tile>")?;
+        }
+        writeln!(writer, " </tileset>")?;
+    }
+    for (i, layer) in map.layers.iter().enumerate() {
+        writeln!(writer,
+                 " <layer id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\">",
+                 i + 1,
+                 escape(&layer.name),
+                 map.width,
+                 map.height)?;
+        writeln!(writer, "  <data encoding=\"csv\">")?;
+        for (row, cells) in layer.data.chunks(map.width as usize).enumerate() {
+            let line = cells.iter().map(|gid| gid.to_string()).collect::<Vec<_>>().join(",");
+            let last = (row + 1) * map.width as usize >= layer.data.len();
+            writeln!(writer, "{}{}", line, if last { "" } else { "," })?;
+        }
+        writeln!(writer, "</data>")?;
+        writeln!(writer, " </layer>")?;
+    }
+    writeln!(writer, "</map>")
+}
+
+/// Returns the parsed value of an attribute, if it is there.
+fn attribute<T>(attributes: &HashMap<String, String>,
+                element: &str,
+                name: &str)
+                -> Result<Option<T>, String>
+    where T: FromStr,
+          T::Err: Display
+{
+    match attributes.get(name) {
+        Some(value) => {
+            value.parse::<T>()
+                .map(Some)
+                .map_err(|err| format!("<{}>: invalid {} {:?}: {}", element, name, value, err))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Returns the parsed value of an attribute that must be there.
+fn required_attribute<T>(attributes: &HashMap<String, String>,
+                         element: &str,
+                         name: &str)
+                         -> Result<T, String>
+    where T: FromStr,
+          T::Err: Display
+{
+    attribute(attributes, element, name)?
+        .ok_or_else(|| format!("<{}>: missing attribute {:?}", element, name))
+}
+
+/// Parses comma-separated tile ids.
+fn parse_csv(text: &str) -> Result<Vec<u32>, String> {
+    text.split(',')
+        .map(|gid| gid.trim())
+        .filter(|gid| !gid.is_empty())
+        .map(|gid| gid.parse::<u32>().map_err(|err| format!("invalid tile id {:?}: {}", gid, err)))
+        .collect()
+}
+
+/// Reads a TMX map with embedded tilesets and layers that are either CSV encoded or
+/// given as `<tile>` elements.
+pub fn read_tmx<R>(reader: R) -> Result<Map, String>
+    where R: Read
+{
+    let mut map = Map {
+        width: 0,
+        height: 0,
+        tile_size: (0, 0),
+        properties: Properties::new(),
+        tilesets: Vec::new(),
+        layers: Vec::new(),
+    };
+    let mut seen_map = false;
+    let mut stack: Vec<String> = Vec::new();
+    let mut csv = None;
+
+    for event in EventReader::new(reader) {
+        match event.map_err(|err| err.to_string())? {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                let element = name.local_name;
+                let attributes = attributes.into_iter()
+                    .map(|attr| (attr.name.local_name, attr.value))
+                    .collect::<HashMap<_, _>>();
+                let parent = stack.last().cloned();
+                match (parent.as_ref().map(|p| &p[..]), &element[..]) {
+                    (None, "map") => {
+                        seen_map = true;
+                        if let Some(orientation) = attributes.get("orientation") {
+                            if orientation != "orthogonal" {
+                                return Err(format!("{} maps are not supported", orientation));
+                            }
+                        }
+                        if attributes.get("infinite").map_or(false, |value| value == "1") {
+                            return Err("infinite maps are not supported".to_owned());
+                        }
+                        map.width = required_attribute(&attributes, "map", "width")?;
+                        map.height = required_attribute(&attributes, "map", "height")?;
+                        map.tile_size = (required_attribute(&attributes, "map", "tilewidth")?,
+                                         required_attribute(&attributes, "map", "tileheight")?);
+                    }
+                    (Some("map"), "tileset") => {
+                        if let Some(source) = attributes.get("source") {
+                            return Err(format!("external tileset {:?} is not supported", source));
+                        }
+                        map.tilesets.push(Tileset {
+                            first_gid: required_attribute(&attributes, "tileset", "firstgid")?,
+                            name: attributes.get("name").cloned().unwrap_or_else(String::new),
+                            tiles: Vec::new(),
+                        });
+                    }
+                    (Some("tileset"), "tile") => {
+                        let id = required_attribute(&attributes, "tile", "id")?;
+                        let size = (attribute(&attributes, "tile", "width")?,
+                                    attribute(&attributes, "tile", "height")?);
+                        let area = match size {
+                            (Some(w), Some(h)) => {
+                                Some((attribute(&attributes, "tile", "x")?.unwrap_or(0),
+                                      attribute(&attributes, "tile", "y")?.unwrap_or(0),
+                                      w,
+                                      h))
+                            }
+                            _ => None,
+                        };
+                        if let Some(tileset) = map.tilesets.last_mut() {
+                            tileset.tiles.push(TilesetTile {
+                                id: id,
+                                image: String::new(),
+                                area: area,
+                                properties: Properties::new(),
+                            });
+                        }
+                    }
+                    (Some("tile"), "image") => {
+                        let source = attributes.get("source").cloned().unwrap_or_else(String::new);
+                        if let Some(tile) = map.tilesets
+                            .last_mut()
+                            .and_then(|tileset| tileset.tiles.last_mut()) {
+                            tile.image = source;
+                        }
+                    }
+                    (Some("properties"), "property") => {
+                        let name = required_attribute::<String>(&attributes, "property", "name")?;
+                        let value = attributes.get("value").cloned().unwrap_or_else(String::new);
+                        let value = match attributes.get("type").map(|kind| &kind[..]) {
+                            Some("int") => {
+                                Property::Int(value.parse::<i64>()
+                                    .map_err(|err| {
+                                        format!("property {:?}: invalid int {:?}: {}",
+                                                name,
+                                                value,
+                                                err)
+                                    })?)
+                            }
+                            _ => Property::Str(value),
+                        };
+                        let owner = stack.iter().rev().nth(1).map(|owner| &owner[..]);
+                        let properties = match owner {
+                            Some("map") => Some(&mut map.properties),
+                            Some("tile") => {
+                                map.tilesets
+                                    .last_mut()
+                                    .and_then(|tileset| tileset.tiles.last_mut())
+                                    .map(|tile| &mut tile.properties)
+                            }
+                            _ => None,
+                        };
+                        if let Some(properties) = properties {
+                            properties.insert(name, value);
+                        }
+                    }
+                    (Some("map"), "layer") => {
+                        let size = (attribute::<u32>(&attributes, "layer", "width")?,
+                                    attribute::<u32>(&attributes, "layer", "height")?);
+                        if size.0.map_or(false, |w| w != map.width) ||
+                           size.1.map_or(false, |h| h != map.height) {
+                            return Err("layers of a different size than the map are not \
+                                        supported"
+                                .to_owned());
+                        }
+                        map.layers.push(TileLayer {
+                            name: attributes.get("name").cloned().unwrap_or_else(String::new),
+                            data: Vec::new(),
+                        });
+                    }
+                    (Some("layer"), "data") => {
+                        if let Some(compression) = attributes.get("compression") {
+                            return Err(format!("{} compression is not supported", compression));
+                        }
+                        csv = match attributes.get("encoding").map(|e| &e[..]) {
+                            None => None,
+                            Some("csv") => Some(String::new()),
+                            Some(other) => {
+                                return Err(format!("{} encoding is not supported", other))
+                            }
+                        };
+                    }
+                    (Some("data"), "tile") => {
+                        let gid = attribute(&attributes, "tile", "gid")?.unwrap_or(0);
+                        if let Some(layer) = map.layers.last_mut() {
+                            layer.data.push(gid);
+                        }
+                    }
+                    (Some("data"), "chunk") => {
+                        return Err("infinite maps are not supported".to_owned());
+                    }
+                    _ => {}
+                }
+                stack.push(element);
+            }
+            XmlEvent::EndElement { .. } => {
+                if stack.pop().map_or(false, |element| element == "data") {
+                    if let Some(text) = csv.take() {
+                        let gids = parse_csv(&text)?;
+                        if let Some(layer) = map.layers.last_mut() {
+                            layer.data = gids;
+                        }
+                    }
+                }
+            }
+            XmlEvent::Characters(text) => {
+                if let Some(ref mut csv) = csv {
+                    csv.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+    if !seen_map {
+        return Err("not a TMX map".to_owned());
+    }
+    Ok(map)
+}
+
+/// Builds a JSON object from its entries.
+fn object(entries: Vec<(&str, Value)>) -> Value {
+    Value::Object(entries.into_iter().map(|(key, value)| (key.to_owned(), value)).collect())
+}
+
+#[inline]
+fn string(text: &str) -> Value {
+    Value::String(text.to_owned())
+}
+
+fn json_properties(properties: &Properties) -> Value {
+    Value::Array(properties.iter()
+        .map(|(name, value)| {
+            let (kind, value) = match *value {
+                Property::Str(ref value) => ("string", string(value)),
+                Property::Int(value) => ("int", Value::I64(value)),
+            };
+            object(vec![("name", string(name)), ("type", string(kind)), ("value", value)])
+        })
+        .collect())
+}
+
+/// Writes a map in the JSON format, with tilesets embedded.
+pub fn write_json<W>(map: &Map, writer: &mut W) -> Result<(), String>
+    where W: Write
+{
+    let tilesets = map.tilesets
+        .iter()
+        .map(|tileset| {
+            let tiles = tileset.tiles
+                .iter()
+                .map(|tile| {
+                    let mut entries = vec![("id", Value::U64(tile.id as u64)),
+                                           ("image", string(&tile.image)),
+                                           ("properties", json_properties(&tile.properties))];
+                    if let Some((x, y, w, h)) = tile.area {
+                        entries.push(("x", Value::U64(x as u64)));
+                        entries.push(("y", Value::U64(y as u64)));
+                        entries.push(("width", Value::U64(w as u64)));
+                        entries.push(("height", Value::U64(h as u64)));
+                    }
+                    object(entries)
+                })
+                .collect();
+            object(vec![("firstgid", Value::U64(tileset.first_gid as u64)),
+                        ("name", string(&tileset.name)),
+                        ("tilewidth", Value::U64(map.tile_size.0 as u64)),
+                        ("tileheight", Value::U64(map.tile_size.1 as u64)),
+                        ("tilecount", Value::U64(tileset.tiles.len() as u64)),
+                        ("columns", Value::U64(0)),
+                        ("margin", Value::U64(0)),
+                        ("spacing", Value::U64(0)),
+                        ("tiles", Value::Array(tiles))])
+        })
+        .collect();
+    let layers = map.layers
+        .iter()
+        .enumerate()
+        .map(|(i, layer)| {
+            object(vec![("id", Value::U64(i as u64 + 1)),
+                        ("type", string("tilelayer")),
+                        ("name", string(&layer.name)),
+                        ("x", Value::U64(0)),
+                        ("y", Value::U64(0)),
+                        ("width", Value::U64(map.width as u64)),
+                        ("height", Value::U64(map.height as u64)),
+                        ("opacity", Value::U64(1)),
+                        ("visible", Value::Bool(true)),
+                        ("data",
+                         Value::Array(layer.data
+                             .iter()
+                             .map(|&gid| Value::U64(gid as u64))
+                             .collect()))])
+        })
+        .collect();
+    let root = object(vec![("type", string("map")),
+                           ("version", string("1.10")),
+                           ("orientation", string("orthogonal")),
+                           ("renderorder", string("right-down")),
+                           ("infinite", Value::Bool(false)),
+                           ("width", Value::U64(map.width as u64)),
+                           ("height", Value::U64(map.height as u64)),
+                           ("tilewidth", Value::U64(map.tile_size.0 as u64)),
+                           ("tileheight", Value::U64(map.tile_size.1 as u64)),
+                           ("nextlayerid", Value::U64(map.layers.len() as u64 + 1)),
+                           ("nextobjectid", Value::U64(1)),
+                           ("properties", json_properties(&map.properties)),
+                           ("tilesets", Value::Array(tilesets)),
+                           ("layers", Value::Array(layers))]);
+    json::to_writer_pretty(writer, &root).map_err(|err| err.to_string())?;
+    writeln!(writer, "").map_err(|err| err.to_string())
+}
+
+/// Returns a number from a JSON object.
+fn json_number(object: &BTreeMap<String, Value>,
+               name: &str,
+               context: &str)
+               -> Result<Option<u32>, String> {
+    match object.get(name) {
+        Some(value) => {
+            match value.as_u64() {
+                Some(n) if n <= u32::max_value() as u64 => Ok(Some(n as u32)),
+                _ => Err(format!("{}: invalid {}", context, name)),
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+/// Returns a number from a JSON object that must be there.
+fn required_json_number(object: &BTreeMap<String, Value>,
+                        name: &str,
+                        context: &str)
+                        -> Result<u32, String> {
+    json_number(object, name, context)?.ok_or_else(|| format!("{}: missing {}", context, name))
+}
+
+/// Reads custom properties, given either as a list (Tiled 1.2 and later) or as an object.
+fn read_json_properties(value: Option<&Value>, context: &str) -> Result<Properties, String> {
+    let mut properties = Properties::new();
+    let entries = match value {
+        None => return Ok(properties),
+        Some(&Value::Array(ref list)) => {
+            list.iter()
+                .map(|item| {
+                    let item = item.as_object();
+                    let name = item.and_then(|item| item.get("name")).and_then(|n| n.as_str());
+                    match (name, item.and_then(|item| item.get("value"))) {
+                        (Some(name), Some(value)) => Ok((name, value)),
+                        _ => Err(format!("{}: invalid property", context)),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        Some(&Value::Object(ref object)) => {
+            object.iter().map(|(name, value)| (&name[..], value)).collect()
+        }
+        Some(_) => return Err(format!("{}: invalid properties", context)),
+    };
+    for (name, value) in entries {
+        let value = match *value {
+            Value::I64(n) => Property::Int(n),
+            Value::U64(n) => Property::Int(n as i64),
+            Value::String(ref text) => Property::Str(text.clone()),
+            // Other kinds of properties mean nothing to levels.
+            _ => continue,
+        };
+        properties.insert(name.to_owned(), value);
+    }
+    Ok(properties)
+}
+
+/// Reads a map in the JSON format with embedded tilesets and unencoded layers.
+pub fn read_json<R>(reader: R) -> Result<Map, String>
+    where R: Read
+{
+    let root: Value = json::from_reader(reader).map_err(|err| err.to_string())?;
+    let root = root.as_object().ok_or("not a Tiled JSON map")?;
+    if let Some(orientation) = root.get("orientation").and_then(|o| o.as_str()) {
+        if orientation != "orthogonal" {
+            return Err(format!("{} maps are not supported", orientation));
+        }
+    }
+    if root.get("infinite").and_then(|i| i.as_boolean()).unwrap_or(false) {
+        return Err("infinite maps are not supported".to_owned());
+    }
+    let mut map = Map {
+        width: required_json_number(root, "width", "map")?,
+        height: required_json_number(root, "height", "map")?,
+        tile_size: (required_json_number(root, "tilewidth", "map")?,
+                    required_json_number(root, "tileheight", "map")?),
+        properties: read_json_properties(root.get("properties"), "map")?,
+        tilesets: Vec::new(),
+        layers: Vec::new(),
+    };
+
+    let tilesets = root.get("tilesets").and_then(|t| t.as_array()).map_or(&[][..], |t| &t[..]);
+    for tileset in tilesets {
+        let tileset = tileset.as_object().ok_or("invalid tileset")?;
+        if let Some(source) = tileset.get("source").and_then(|s| s.as_str()) {
+            return Err(format!("external tileset {:?} is not supported", source));
+        }
+        let name = tileset.get("name").and_then(|n| n.as_str()).unwrap_or("").to_owned();
+        let context = format!("tileset {:?}", name);
+        let mut tiles = Vec::new();
+        let entries = tileset.get("tiles").and_then(|t| t.as_array()).map_or(&[][..], |t| &t[..]);
+        for tile in entries {
+            let tile = tile.as_object().ok_or_else(|| format!("{}: invalid tile", context))?;
+            let id = required_json_number(tile, "id", &context)?;
+            let tile_context = format!("tile {} of {}", id, context);
+            let area = match (json_number(tile, "width", &tile_context)?,
+                              json_number(tile, "height", &tile_context)?) {
+                (Some(w), Some(h)) => {
+                    Some((json_number(tile, "x", &tile_context)?.unwrap_or(0),
+                          json_number(tile, "y", &tile_context)?.unwrap_or(0),
+                          w,
+                          h))
+                }
+                _ => None,
+            };
+            tiles.push(TilesetTile {
+                id: id,
+                image: tile.get("image").and_then(|i| i.as_str()).unwrap_or("").to_owned(),
+                area: area,
+                properties: read_json_properties(tile.get("properties"), &tile_context)?,
+            });
+        }
+        map.tilesets.push(Tileset {
+            first_gid: required_json_number(tileset, "firstgid", &context)?,
+            name: name,
+            tiles: tiles,
+        });
+    }
+
+    let layers = root.get("layers").and_then(|l| l.as_array()).map_or(&[][..], |l| &l[..]);
+    for layer in layers {
+        let layer = layer.as_object().ok_or("invalid layer")?;
+        // Object, image and group layers hold nothing that levels can use.
+        if layer.get("type").and_then(|t| t.as_str()) != Some("tilelayer") {
+            continue;
+        }
+        let name = layer.get("name").and_then(|n| n.as_str()).unwrap_or("").to_owned();
+        let context = format!("layer {:?}", name);
+        if let Some(encoding) = layer.get("encoding").and_then(|e| e.as_str()) {
+            if encoding != "csv" {
+                return Err(format!("{}: {} encoding is not supported", context, encoding));
+            }
+        }
+        let data = layer.get("data")
+            .and_then(|d| d.as_array())
+            .ok_or_else(|| format!("{}: missing data", context))?
+            .iter()
+            .map(|gid| match gid.as_u64() {
+                Some(gid) if gid <= u32::max_value() as u64 => Ok(gid as u32),
+                _ => Err(format!("{}: invalid tile id", context)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        map.layers.push(TileLayer {
+            name: name,
+            data: data,
+        });
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use glorious::Color;
+
+    use info::{Schema, SpriteInfo};
+    use level::{Layer, Level, Tile};
+
+    use super::{from_map, read_json, read_tmx, relative_path, to_map, write_json, write_tmx};
+
+    fn sprite(texture: &str, area: Option<(u32, u32, u32, u32)>) -> SpriteInfo {
+        SpriteInfo {
+            texture: texture.to_owned(),
+            area: area,
+            layers: None,
+        }
+    }
+
+    fn schema() -> Schema {
+        let mut tiles = HashMap::new();
+        tiles.insert("wall".to_owned(), sprite("wall.png", None));
+        tiles.insert("coin".to_owned(), sprite("items.png", Some((0, 0, 16, 16))));
+        tiles.insert("key".to_owned(), sprite("items.png", Some((16, 0, 16, 16))));
+        Schema {
+            name: "test".to_owned(),
+            layers: vec!["ground".to_owned(), "items".to_owned(), "empty".to_owned()],
+            prefix: PathBuf::from("assets"),
+            font: None,
+            colors: vec![Color(0xff, 0x00, 0x00, 0xff),
+                         Color(0x00, 0xff, 0x00, 0xff),
+                         Color(0x00, 0x00, 0xff, 0xff)],
+            tiles: tiles,
+        }
+    }
+
+    fn level() -> Level {
+        let mut level = Level::new("round trip", "test");
+        level.size = Some((12, 7));
+        let mut ground = Layer::new();
+        for x in -3..4 {
+            ground.set((x, -2), Tile::new("wall", (x + 3) as u32 % 3));
+        }
+        ground.set((-3, 5), Tile::new("wall", 2));
+        let mut items = Layer::new();
+        items.set((0, 0), Tile::new("coin", 1));
+        items.set((-1, 3), Tile::new("key", 2));
+        items.set((2, -1), Tile::new("coin", 0));
+        level.layers.insert("ground".to_owned(), ground);
+        level.layers.insert("items".to_owned(), items);
+        level.layers.insert("empty".to_owned(), Layer::new());
+        level
+    }
+
+    #[test]
+    fn tmx_round_trip() {
+        let (schema, level) = (schema(), level());
+        let map = to_map(&level, &schema, Path::new("maps"), 16).unwrap();
+        let mut buffer = Vec::new();
+        write_tmx(&map, &mut buffer).unwrap();
+        let read = read_tmx(&buffer[..]).unwrap();
+        assert_eq!(read, map);
+        assert_eq!(from_map(&read, &schema).unwrap(), level);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let (schema, level) = (schema(), level());
+        let map = to_map(&level, &schema, Path::new("maps"), 16).unwrap();
+        let mut buffer = Vec::new();
+        write_json(&map, &mut buffer).unwrap();
+        let read = read_json(&buffer[..]).unwrap();
+        assert_eq!(read, map);
+        assert_eq!(from_map(&read, &schema).unwrap(), level);
+    }
+
+    #[test]
+    fn image_paths_are_relative_to_the_map() {
+        let (schema, level) = (schema(), level());
+        let map = to_map(&level, &schema, Path::new("maps/forest"), 16).unwrap();
+        let image = PathBuf::from(&map.tilesets[0].tiles[0].image);
+        assert_eq!(image, Path::new("../../assets/items.png"));
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative_path(Path::new("/a/b"), Path::new("/a/c/d")),
+                   Path::new("../c/d"));
+        assert_eq!(relative_path(Path::new("./a"), Path::new("a/b")), Path::new("b"));
+        assert_eq!(relative_path(Path::new(""), Path::new("assets")), Path::new("assets"));
+    }
+}
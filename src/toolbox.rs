@@ -1,4 +1,98 @@
-#[derive(Clone, Copy, Debug, PartialEq)]
+use std::cmp;
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Tool {
     Paint,
+    Rect,
+    Fill,
+    Line,
+    Select,
+}
+
+/// Returns the inclusive bounds `(x, y, x', y')` of the cells spanned by two corners.
+#[inline]
+pub fn span(a: (i32, i32), b: (i32, i32)) -> (i32, i32, i32, i32) {
+    (cmp::min(a.0, b.0), cmp::min(a.1, b.1), cmp::max(a.0, b.0), cmp::max(a.1, b.1))
+}
+
+/// Returns whether a cell lies within the inclusive bounds `(x, y, x', y')`.
+#[inline]
+pub fn contains(span: (i32, i32, i32, i32), pos: (i32, i32)) -> bool {
+    span.0 <= pos.0 && pos.0 <= span.2 && span.1 <= pos.1 && pos.1 <= span.3
+}
+
+/// Returns every cell in the rectangle spanned by two corners.
+pub fn rect_cells(a: (i32, i32), b: (i32, i32)) -> Vec<(i32, i32)> {
+    let (x1, y1, x2, y2) = span(a, b);
+    let mut cells = Vec::new();
+    for y in y1..y2 + 1 {
+        for x in x1..x2 + 1 {
+            cells.push((x, y));
+        }
+    }
+    cells
+}
+
+/// Returns the 4-connected region of cells around `start` for which `same` holds.
+///
+/// The search never leaves the given inclusive bounds `(x, y, x', y')`.
+pub fn flood_fill<F>(start: (i32, i32),
+                     bounds: (i32, i32, i32, i32),
+                     mut same: F)
+                     -> Vec<(i32, i32)>
+    where F: FnMut((i32, i32)) -> bool
+{
+    let (x1, y1, x2, y2) = bounds;
+    let inside = |(x, y): (i32, i32)| x1 <= x && x <= x2 && y1 <= y && y <= y2;
+
+    let mut region = Vec::new();
+    if !inside(start) || !same(start) {
+        return region;
+    }
+    let mut seen = HashSet::new();
+    let mut stack = vec![start];
+    seen.insert(start);
+    while let Some(pos) = stack.pop() {
+        region.push(pos);
+        let (x, y) = pos;
+        for &next in &[(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+            if inside(next) && !seen.contains(&next) {
+                seen.insert(next);
+                if same(next) {
+                    stack.push(next);
+                }
+            }
+        }
+    }
+    region
+}
+
+/// Returns the cells on the straight line between two cells, both included.
+///
+/// Uses Bresenham's algorithm, so consecutive cells always touch.
+pub fn line_cells(a: (i32, i32), b: (i32, i32)) -> Vec<(i32, i32)> {
+    let dx = (b.0 - a.0).abs();
+    let dy = -(b.1 - a.1).abs();
+    let sx = if a.0 < b.0 { 1 } else { -1 };
+    let sy = if a.1 < b.1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    let (mut x, mut y) = a;
+    let mut cells = Vec::new();
+    loop {
+        cells.push((x, y));
+        if (x, y) == b {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+    cells
 }
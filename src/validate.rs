@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use sdl2::surface::Surface;
+use sdl2_image::LoadSurface;
+
+use info::Schema;
+use level::{self, RawLevel};
+
+/// Where in a level file a tile entry is found.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub layer: String,
+    pub tile: String,
+    /// The index of the entry in the list of the tile.
+    pub index: usize,
+    pub pos: (i32, i32),
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "layers.{}.{}[{}] at ({}, {})",
+               self.layer,
+               self.tile,
+               self.index,
+               self.pos.0,
+               self.pos.1)
+    }
+}
+
+/// Something wrong with a level, relative to its schema.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Problem {
+    SchemaMismatch { level: String, schema: String },
+    UnknownLayer(String),
+    UnknownTile(Location),
+    ColorOutOfRange(Location, u32),
+    TileNotAllowed(Location),
+    DuplicatePosition(Location, Location),
+    OutOfBounds(Location, (u32, u32)),
+}
+
+impl Problem {
+    /// Returns whether the editor cannot open a level with this problem.
+    pub fn prevents_editing(&self) -> bool {
+        match *self {
+            Problem::DuplicatePosition(..) |
+            Problem::TileNotAllowed(..) |
+            Problem::OutOfBounds(..) => false,
+            _ => true,
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::SchemaMismatch { ref level, ref schema } => {
+                write!(f, "schema: level uses {:?}, but the schema is {:?}", level, schema)
+            }
+            Problem::UnknownLayer(ref layer) => {
+                write!(f, "layers.{}: layer not known to schema", layer)
+            }
+            Problem::UnknownTile(ref loc) => write!(f, "{}: tile not known to schema", loc),
+            Problem::ColorOutOfRange(ref loc, color) => {
+                write!(f, "{}: color {} is out of range", loc, color)
+            }
+            Problem::TileNotAllowed(ref loc) => {
+                write!(f, "{}: tile may not be placed in this layer", loc)
+            }
+            Problem::DuplicatePosition(ref loc, ref first) => {
+                write!(f, "{}: position already used by {}", loc, first)
+            }
+            Problem::OutOfBounds(ref loc, (w, h)) => {
+                write!(f, "{}: position lies outside the {}x{} level", loc, w, h)
+            }
+        }
+    }
+}
+
+/// Checks the level file at the given path against a schema.
+///
+/// The problems are sorted by kind, and then by their location in the file.
+pub fn validate_file<P>(path: P, schema: &Schema) -> Result<Vec<Problem>, level::Error>
+    where P: AsRef<Path>
+{
+    let raw = RawLevel::load(path)?;
+    Ok(validate(&raw, schema))
+}
+
+fn validate(level: &RawLevel, schema: &Schema) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if level.schema != schema.name {
+        problems.push(Problem::SchemaMismatch {
+            level: level.schema.clone(),
+            schema: schema.name.clone(),
+        });
+    }
+    for (layer_name, layer) in &level.layers {
+        if !schema.layers.contains(layer_name) {
+            problems.push(Problem::UnknownLayer(layer_name.clone()));
+        }
+        let mut tiles = layer.iter().collect::<Vec<_>>();
+        tiles.sort_by(|a, b| a.0.cmp(b.0));
+        let mut seen = HashMap::new();
+        for (tile, entries) in tiles {
+            let info = schema.tiles.get(tile);
+            for (index, &(x, y, color)) in entries.iter().enumerate() {
+                let loc = Location {
+                    layer: layer_name.clone(),
+                    tile: tile.clone(),
+                    index: index,
+                    pos: (x, y),
+                };
+                match info {
+                    None => problems.push(Problem::UnknownTile(loc.clone())),
+                    Some(info) if !info.allows_layer(layer_name) => {
+                        problems.push(Problem::TileNotAllowed(loc.clone()))
+                    }
+                    Some(_) => {}
+                }
+                if color as usize >= schema.colors.len() {
+                    problems.push(Problem::ColorOutOfRange(loc.clone(), color));
+                }
+                if let Some((w, h)) = level.size {
+                    if x < 0 || y < 0 || x as u32 >= w || y as u32 >= h {
+                        problems.push(Problem::OutOfBounds(loc.clone(), (w, h)));
+                    }
+                }
+                match seen.get(&(x, y)).cloned() {
+                    Some(first) => problems.push(Problem::DuplicatePosition(loc, first)),
+                    None => {
+                        seen.insert((x, y), loc);
+                    }
+                }
+            }
+        }
+    }
+    problems.sort();
+    problems
+}
+
+/// Something wrong with a schema, found by looking at its tiles and textures.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SchemaProblem {
+    MissingTexture { tile: String, path: String },
+    UnsupportedTexture { tile: String, path: String },
+    UndecodableTexture { tile: String, path: String, error: String },
+    AreaOutOfBounds {
+        tile: String,
+        area: (u32, u32, u32, u32),
+        size: (u32, u32),
+    },
+    UnknownTileLayer { tile: String, layer: String },
+    UnusableTile(String),
+}
+
+impl Display for SchemaProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SchemaProblem::*;
+        match *self {
+            MissingTexture { ref tile, ref path } => {
+                write!(f, "tiles.{}: texture {:?} does not exist", tile, path)
+            }
+            UnsupportedTexture { ref tile, ref path } => {
+                write!(f, "tiles.{}: texture {:?} is not a PNG or JPG file", tile, path)
+            }
+            UndecodableTexture { ref tile, ref path, ref error } => {
+                write!(f, "tiles.{}: texture {:?} could not be read: {}", tile, path, error)
+            }
+            AreaOutOfBounds { ref tile, area, size } => {
+                write!(f,
+                       "tiles.{}: area {:?} lies outside the {}x{} texture",
+                       tile,
+                       area,
+                       size.0,
+                       size.1)
+            }
+            UnknownTileLayer { ref tile, ref layer } => {
+                write!(f, "tiles.{}: layer {:?} not known to schema", tile, layer)
+            }
+            UnusableTile(ref tile) => write!(f, "tiles.{}: no layer can hold this tile", tile),
+        }
+    }
+}
+
+/// Checks that every texture of a schema exists and can be decoded, that sprite areas
+/// lie within their textures, and that every tile can be placed in some layer.
+///
+/// SDL2_image must be initialized, but no window is needed.
+pub fn validate_schema(schema: &Schema) -> Vec<SchemaProblem> {
+    let mut problems = Vec::new();
+    let mut sizes = HashMap::new();
+    for (tile, info) in &schema.tiles {
+        let path = schema.prefix.join(&info.texture);
+        if !sizes.contains_key(&path) {
+            let size = texture_size(&path);
+            sizes.insert(path.clone(), size);
+        }
+        let display = path.display().to_string();
+        match (&sizes[&path], info.area) {
+            (&Err(TextureError::Missing), _) => {
+                problems.push(SchemaProblem::MissingTexture {
+                    tile: tile.clone(),
+                    path: display,
+                })
+            }
+            (&Err(TextureError::Unsupported), _) => {
+                problems.push(SchemaProblem::UnsupportedTexture {
+                    tile: tile.clone(),
+                    path: display,
+                })
+            }
+            (&Err(TextureError::Decode(ref error)), _) => {
+                problems.push(SchemaProblem::UndecodableTexture {
+                    tile: tile.clone(),
+                    path: display,
+                    error: error.clone(),
+                })
+            }
+            (&Ok((w, h)), Some(area)) => {
+                let (x, y, aw, ah) = area;
+                if aw == 0 || ah == 0 || x as u64 + aw as u64 > w as u64 ||
+                   y as u64 + ah as u64 > h as u64 {
+                    problems.push(SchemaProblem::AreaOutOfBounds {
+                        tile: tile.clone(),
+                        area: area,
+                        size: (w, h),
+                    });
+                }
+            }
+            (&Ok(_), None) => {}
+        }
+
+        if let Some(ref layers) = info.layers {
+            for layer in layers {
+                if !schema.layers.contains(layer) {
+                    problems.push(SchemaProblem::UnknownTileLayer {
+                        tile: tile.clone(),
+                        layer: layer.clone(),
+                    });
+                }
+            }
+        }
+        if !schema.layers.iter().any(|layer| info.allows_layer(layer)) {
+            problems.push(SchemaProblem::UnusableTile(tile.clone()));
+        }
+    }
+    problems.sort();
+    problems
+}
+
+enum TextureError {
+    Missing,
+    Unsupported,
+    Decode(String),
+}
+
+fn texture_size(path: &Path) -> Result<(u32, u32), TextureError> {
+    if !path.is_file() {
+        return Err(TextureError::Missing);
+    }
+    let extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    match extension.as_ref().map(|ext| &ext[..]) {
+        Some("png") | Some("jpg") | Some("jpeg") => {}
+        _ => return Err(TextureError::Unsupported),
+    }
+    let surface = Surface::from_file(path).map_err(TextureError::Decode)?;
+    Ok((surface.width(), surface.height()))
+}